@@ -4,36 +4,265 @@ use std::str::FromStr;
 
 use log::Level as LogLevel;
 use regex::Regex;
-use structopt::StructOpt;
+use structopt::{clap::AppSettings, StructOpt};
+use url::Url;
 
-use crate::req_resp::ResponderBehaviour;
+use crate::delivery::{Throttle, UrlThrottle};
+use crate::faults::FaultRule;
+use crate::listener::Listener;
+use crate::req_resp::{parse_origin, DuplicatePolicy, OriginRewrite, ResponderBehaviour};
+
+/// Largest latency scale, slowing a one second timing down to over a quarter of an hour
+const MAX_SCALE: f64 = 1000.0;
+
+fn parse_scale(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(scale) if (0.0..=MAX_SCALE).contains(&scale) => Ok(scale),
+        _ => Err(format!(
+            "Invalid scale {:?}, expected a number from 0 to {}",
+            s, MAX_SCALE
+        )),
+    }
+}
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "harPlay", about = "Run a webserver out of a HAR file")]
+#[structopt(
+    name = "harPlay",
+    about = "Run a webserver out of a HAR file",
+    setting = AppSettings::SubcommandsNegateReqs,
+    setting = AppSettings::ArgsNegateSubcommands
+)]
 pub struct CliArgs {
-    #[structopt(parse(from_os_str))]
-    pub har_file: PathBuf,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+
+    /// HAR files to serve, optionally compressed with gzip or zstd, or zipped;
+    /// directories are searched for `*.har` files and zip archives recursively
+    #[structopt(parse(from_os_str), required = true)]
+    pub har_files: Vec<PathBuf>,
 
+    /// Reload the HAR files whenever they change, without restarting the
+    /// server; sequences of responses start over. Only the HAR files are
+    /// watched: other options, like TLS certificates, need a restart
+    #[structopt(long)]
+    pub watch: bool,
+
+    /// What to do with requests found in more than one HAR file
     #[structopt(
-        short,
         long,
-        parse(try_from_str),
-        default_value = "sequential-once",
-        possible_values = ResponderBehaviour::variants()
+        default_value = "append",
+        possible_values = DuplicatePolicy::variants()
     )]
-    pub behaviour: ResponderBehaviour,
+    pub duplicates: DuplicatePolicy,
+
+    /// Work around entries that don't quite follow the HAR spec (missing fields,
+    /// mistyped numbers), warning about each problem, and skip the hopeless ones
+    #[structopt(long)]
+    pub lenient: bool,
+
+    /// Entry to serve out of zipped HAR files, instead of the first `.har` one
+    #[structopt(long)]
+    pub zip_entry: Option<String>,
 
+    /// How to pick among the responses recorded for the same request, as
+    /// they get requested again (by default, in order and starting over
+    /// after the last one, so reloading a page keeps working)
     #[structopt(
         short,
         long,
-        default_value = "127.0.0.1:3030",
-        parse(try_from_str = SocketAddr::from_str)
+        parse(try_from_str),
+        default_value = "sequential-wrapping",
+        possible_values = ResponderBehaviour::variants()
     )]
-    pub network_bind: SocketAddr,
+    pub behaviour: ResponderBehaviour,
+
+    /// Address to listen on, as `ADDR[=ORIGIN]`, serving only the requests
+    /// recorded for ORIGIN when given, and the rest otherwise (`:PORT` listens
+    /// on 127.0.0.1)
+    #[structopt(short, long, number_of_values = 1, default_value = "127.0.0.1:3030")]
+    pub network_bind: Vec<Listener>,
+
+    /// Serve HTTPS with this PEM certificate chain, instead of plain HTTP
+    #[structopt(long, parse(from_os_str), requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key (PKCS#8 or RSA) of `--tls-cert`
+    #[structopt(long, parse(from_os_str), requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Serve HTTPS with a self-signed certificate made up on start
+    #[structopt(long, conflicts_with = "tls-cert")]
+    pub tls_self_signed: bool,
+
+    /// Hostname (or IP address) the self-signed certificate is for, instead of
+    /// `localhost` and the bound address
+    #[structopt(long, number_of_values = 1, requires = "tls-self-signed")]
+    pub tls_hostname: Vec<String>,
+
+    /// Most HTTP/2 streams a client may have open at once on a connection
+    #[structopt(long)]
+    pub http2_max_concurrent_streams: Option<u32>,
+
+    /// Work as an HTTP forward proxy, telling requests apart by their full
+    /// origin, and intercepting HTTPS with certificates from `--proxy-ca`
+    #[structopt(long)]
+    pub proxy: bool,
+
+    /// Directory with the CA (`ca.pem` and `ca-key.pem`) making up certificates
+    /// for intercepted HTTPS, created if missing; clients have to trust `ca.pem`.
+    /// Defaults to `harplay/ca` in the user's configuration directory
+    #[structopt(long, parse(from_os_str))]
+    pub proxy_ca: Option<PathBuf>,
 
     #[structopt(short, long, parse(try_from_str = Regex::new))]
     pub url_filter: Option<Regex>,
 
+    /// Leave out entries the browser served from its cache, as recorded by Chrome
+    #[structopt(long)]
+    pub skip_cached: bool,
+
+    /// Only serve entries of this resource type (document, script, xhr...), as
+    /// recorded by Chrome; entries without one (from other exporters) are left out
+    #[structopt(long, number_of_values = 1)]
+    pub resource_type: Vec<String>,
+
     #[structopt(short, long)]
     pub log_level: Option<LogLevel>,
+
+    /// Rewrite absolute URLs of a recorded origin inside textual response bodies,
+    /// as `ORIGIN[=TARGET]` (TARGET defaults to this server's origin)
+    #[structopt(long, number_of_values = 1)]
+    pub rewrite_origin: Vec<OriginRewrite>,
+
+    /// Rewrite every origin recorded in the HAR file to this server's origin
+    #[structopt(long)]
+    pub rewrite_all_origins: bool,
+
+    /// The origin clients reach the first listener at, when it is not its
+    /// address (as behind a reverse proxy, or in a container), for rewriting
+    #[structopt(long, parse(try_from_str = parse_origin))]
+    pub public_origin: Option<String>,
+
+    /// Hold responses back by their recorded timings
+    #[structopt(long)]
+    pub latency: bool,
+
+    /// Multiply recorded timings by this factor (implies `--latency`)
+    #[structopt(long, parse(try_from_str = parse_scale))]
+    pub latency_scale: Option<f64>,
+
+    /// Emulate a slow network for every response: a profile (3g, slow-4g) or KBPS:RTT_MS
+    #[structopt(long)]
+    pub throttle: Option<Throttle>,
+
+    /// Emulate a slow network for matching URLs, as REGEX=PROFILE (overrides
+    /// `--throttle`); the URL is the recorded one, origin included when known
+    #[structopt(long, number_of_values = 1)]
+    pub throttle_url: Vec<UrlThrottle>,
+
+    /// Send Server-Sent Events and chunked responses piece by piece, spread over
+    /// their recorded timings
+    #[structopt(long)]
+    pub stream: bool,
+
+    /// Milliseconds between streamed pieces, instead of the recorded timings
+    /// (implies `--stream`)
+    #[structopt(long)]
+    pub stream_interval: Option<u64>,
+
+    /// Keep response bodies over this many bytes in a temporary file instead of
+    /// in memory, for very large HAR files
+    #[structopt(long)]
+    pub spill_bodies_over: Option<usize>,
+
+    /// Hold WebSocket replays back until the client sends each recorded client
+    /// frame, timing the following frames from then
+    #[structopt(long)]
+    pub ws_wait_for_client: bool,
+
+    /// Inject a fault, as `[REGEX=]KIND[@PERCENT%]`; KIND is one of status:CODE,
+    /// reset, truncate, delay:MIN_MS-MAX_MS or wrong-variant, and REGEX matches
+    /// the recorded URL, origin included when known
+    #[structopt(long, number_of_values = 1)]
+    pub fault: Vec<FaultRule>,
+
+    /// Seed for picking faults, to make runs repeatable
+    #[structopt(long)]
+    pub fault_seed: Option<u64>,
+
+    /// Answer conditional requests with `304 Not Modified` when the recorded
+    /// `ETag` or `Last-Modified` validators match
+    #[structopt(long)]
+    pub conditional: bool,
+
+    /// Answer CORS preflights and add `Access-Control-Allow-*` headers to responses
+    #[structopt(long)]
+    pub cors: bool,
+
+    /// Origin allowed in CORS mode (any origin when none is given)
+    #[structopt(long, number_of_values = 1, requires = "cors")]
+    pub cors_origin: Vec<String>,
+
+    /// Method allowed in CORS mode (the requested one when none is given)
+    #[structopt(long, number_of_values = 1, requires = "cors")]
+    pub cors_method: Vec<String>,
+
+    /// Request header allowed in CORS mode (the requested ones when none is given)
+    #[structopt(long, number_of_values = 1, requires = "cors")]
+    pub cors_header: Vec<String>,
+
+    /// Allow credentials (cookies, authorization headers) in CORS mode
+    #[structopt(long, requires = "cors")]
+    pub cors_credentials: bool,
+
+    /// Seconds browsers may cache CORS preflight answers for
+    #[structopt(long, requires = "cors")]
+    pub cors_max_age: Option<u64>,
+
+    /// Forward requests missing from the HAR files to this server, and answer
+    /// with its responses instead of an error
+    #[structopt(long)]
+    pub passthrough: Option<Url>,
+
+    /// Serve passed through responses from then on, and append them to this HAR
+    /// file (created if missing, and kept compressed if `.gz` or `.zst`)
+    #[structopt(long, parse(from_os_str), requires = "passthrough")]
+    pub record_missing: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Check HAR files against the HAR 1.2 spec, reporting every problem found
+    /// with its position, and fail if there are any
+    Validate {
+        /// HAR files to check, optionally compressed or zipped like the served ones;
+        /// directories are searched for `*.har` files and zip archives recursively
+        #[structopt(parse(from_os_str), required = true)]
+        har_files: Vec<PathBuf>,
+
+        /// Entry to check out of zipped HAR files, instead of the first `.har` one
+        #[structopt(long)]
+        zip_entry: Option<String>,
+    },
+
+    /// Forward requests to an upstream server, answer with its responses, and
+    /// append the exchanges to a HAR file
+    Record {
+        /// HAR file to append to, created if missing; `.gz` and `.zst` ones are
+        /// kept compressed
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+
+        /// Base URL of the server to forward requests to
+        #[structopt(long)]
+        upstream: Url,
+
+        #[structopt(
+            short,
+            long,
+            default_value = "127.0.0.1:3030",
+            parse(try_from_str = SocketAddr::from_str)
+        )]
+        network_bind: SocketAddr,
+    },
 }
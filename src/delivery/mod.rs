@@ -0,0 +1,472 @@
+mod streaming;
+mod throttle;
+mod web_socket;
+
+use std::time::Duration;
+
+use hyper::{body::Bytes, upgrade::OnUpgrade, Body as HttpBody, Response as HttpResponse};
+use tokio::time::delay_for;
+use tokio_tungstenite::{tungstenite::protocol::Role, WebSocketStream};
+
+use crate::req_resp::{Request, Response};
+
+use streaming::{is_chunked, is_event_stream, split_events, split_lines};
+pub use throttle::{Throttle, UrlThrottle};
+pub use web_socket::is_upgrade;
+
+/// How often paced bodies get a new chunk
+const TICK: Duration = Duration::from_millis(50);
+
+/// When and how fast a response gets sent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pacing {
+    /// Held back before sending anything
+    pub delay: Duration,
+    /// Time the body gets spread over
+    pub spread: Duration,
+}
+
+/// Turns responder responses into HTTP responses, optionally replaying the
+/// recorded timings and emulating slow networks.
+#[derive(Debug, Clone, Default)]
+pub struct Delivery {
+    latency_scale: Option<f64>,
+    throttle: Option<Throttle>,
+    url_throttles: Vec<UrlThrottle>,
+    streaming: Option<Streaming>,
+    web_socket_wait: bool,
+}
+
+/// How to pace Server-Sent Events and chunked responses
+#[derive(Debug, Clone, PartialEq)]
+pub enum Streaming {
+    /// Spread events over the recorded receiving time
+    Recorded,
+    /// A fixed interval between events
+    Interval(Duration),
+}
+
+impl Delivery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replay recorded timings, multiplied by `scale`
+    pub fn with_latency(mut self, scale: f64) -> Self {
+        self.latency_scale = Some(scale);
+        self
+    }
+
+    /// Throttle every response not matching a URL throttle
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Throttle responses to matching URLs; the first matching one wins
+    pub fn with_url_throttle(mut self, url_throttle: UrlThrottle) -> Self {
+        self.url_throttles.push(url_throttle);
+        self
+    }
+
+    /// Send Server-Sent Events and chunked responses piece by piece
+    pub fn with_streaming(mut self, streaming: Streaming) -> Self {
+        self.streaming = Some(streaming);
+        self
+    }
+
+    /// Hold WebSocket replays back until the client sends the recorded frames
+    pub fn with_web_socket_wait(mut self) -> Self {
+        self.web_socket_wait = true;
+        self
+    }
+
+    fn throttle_for(&self, request: &Request) -> Option<&Throttle> {
+        let url = request.public_url();
+        self.url_throttles
+            .iter()
+            .find(|url_throttle| url_throttle.pattern.is_match(&url))
+            .map(|url_throttle| &url_throttle.throttle)
+            .or(self.throttle.as_ref())
+    }
+
+    pub fn pacing(&self, request: &Request, response: &Response) -> Pacing {
+        let mut pacing = match self.latency_scale {
+            Some(scale) => Pacing {
+                delay: scale_duration(response.timings.wait, scale),
+                spread: scale_duration(response.timings.receive, scale),
+            },
+            None => Pacing::default(),
+        };
+
+        if let Some(throttle) = self.throttle_for(request) {
+            let length = response.body.as_ref().map_or(0, Vec::len);
+            pacing.delay = (pacing.delay + throttle.rtt).min(MAX_PACING);
+            pacing.spread = pacing.spread.max(throttle.transfer_time(length));
+        }
+
+        pacing
+    }
+
+    pub async fn deliver(&self, request: &Request, response: Response) -> HttpResponse<HttpBody> {
+        self.deliver_with(request, response, false).await
+    }
+
+    /// Like `deliver`, but the connection gets cut halfway through the body
+    pub async fn deliver_truncated(
+        &self,
+        request: &Request,
+        response: Response,
+    ) -> HttpResponse<HttpBody> {
+        self.deliver_with(request, response, true).await
+    }
+
+    /// Accept the WebSocket upgrade `request` asks for, and replay the frames
+    /// recorded in `response` once `upgrade` completes
+    pub fn deliver_web_socket(
+        &self,
+        request: &Request,
+        response: Response,
+        upgrade: OnUpgrade,
+    ) -> HttpResponse<HttpBody> {
+        let mut handshake = Response {
+            status_code: 101,
+            headers: Vec::new(),
+            body: None,
+            timings: Default::default(),
+            web_socket_messages: Vec::new(),
+        };
+        handshake.set_header("connection", "Upgrade");
+        handshake.set_header("upgrade", "websocket");
+        handshake.set_header(
+            "sec-websocket-accept",
+            web_socket::accept_key(request.header("sec-websocket-key").unwrap_or_default()),
+        );
+        // Extensions are left out, the replay doesn't speak any of them
+        if let Some(protocol) = response.header("sec-websocket-protocol") {
+            handshake.set_header("sec-websocket-protocol", protocol);
+        }
+
+        let messages = response.web_socket_messages;
+        let wait_for_client = self.web_socket_wait;
+        let url = request.original_url.clone();
+
+        tokio::spawn(async move {
+            match upgrade.await {
+                Ok(upgraded) => {
+                    log::debug!("Replaying {} WebSocket frames to {}", messages.len(), url);
+                    let socket =
+                        WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                    web_socket::replay(socket, messages, wait_for_client).await;
+                }
+                Err(error) => log::warn!("WebSocket upgrade of {} failed: {}", url, error),
+            }
+        });
+
+        handshake.into()
+    }
+
+    async fn deliver_with(
+        &self,
+        request: &Request,
+        mut response: Response,
+        truncate: bool,
+    ) -> HttpResponse<HttpBody> {
+        let pacing = self.pacing(request, &response);
+
+        if pacing.delay > Duration::from_secs(0) {
+            delay_for(pacing.delay).await;
+        }
+
+        let pieces = self.streaming.as_ref().and_then(|streaming| {
+            let body = response.body.as_ref()?;
+            let pieces = if is_event_stream(&response) {
+                split_events(body)
+            } else if is_chunked(&response) {
+                split_lines(body)
+            } else {
+                return None;
+            };
+
+            let interval = match streaming {
+                Streaming::Interval(interval) => *interval,
+                Streaming::Recorded => {
+                    // Already scaled (and throttled) when replaying latency
+                    let receive = match self.latency_scale {
+                        Some(_) => pacing.spread,
+                        None => response.timings.receive.max(pacing.spread),
+                    };
+                    receive / pieces.len().saturating_sub(1).max(1) as u32
+                }
+            };
+
+            Some((pieces, interval))
+        });
+
+        if pieces.is_none() && pacing.spread < TICK && !truncate {
+            return response.into();
+        }
+
+        let body = response.body.take().unwrap_or_default();
+        let truncate_at = if truncate {
+            // Announce the whole length, so clients can tell something went wrong
+            response.set_header("content-length", body.len().to_string());
+            Some(body.len() / 2)
+        } else {
+            None
+        };
+
+        let (pieces, interval) = match pieces {
+            Some(pieces) => {
+                // Streams have no length to announce, hyper takes care of the framing
+                response.remove_header("content-length");
+                response.remove_header("transfer-encoding");
+                pieces
+            }
+            None => {
+                let ticks = (pacing.spread.as_millis() / TICK.as_millis()).max(1) as usize;
+                let chunk_size = (body.len() / ticks).max(1);
+                (body.chunks(chunk_size).map(Vec::from).collect(), TICK)
+            }
+        };
+
+        let (parts, _) = HttpResponse::<HttpBody>::from(response).into_parts();
+        HttpResponse::from_parts(parts, streamed_body(pieces, interval, truncate_at))
+    }
+}
+
+/// Stream `pieces` one by one, `interval` apart, aborting the stream after
+/// `truncate_at` bytes if given
+fn streamed_body(pieces: Vec<Vec<u8>>, interval: Duration, truncate_at: Option<usize>) -> HttpBody {
+    let (mut sender, http_body) = HttpBody::channel();
+
+    tokio::spawn(async move {
+        let mut remaining = truncate_at.unwrap_or(usize::MAX);
+        let mut pieces = pieces.into_iter().peekable();
+
+        while let Some(mut piece) = pieces.next() {
+            if remaining == 0 {
+                break;
+            }
+            piece.truncate(remaining);
+            remaining -= piece.len();
+
+            if sender.send_data(Bytes::from(piece)).await.is_err() {
+                log::debug!("Client went away while receiving a streamed body");
+                return;
+            }
+            if pieces.peek().is_some() {
+                delay_for(interval).await;
+            }
+        }
+
+        if truncate_at.is_some() {
+            sender.abort();
+        }
+    });
+
+    http_body
+}
+
+/// Longest a response is ever held back for; timers panic way past that
+const MAX_PACING: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Multiply `duration` by `scale`, saturating instead of panicking on
+/// overflow (HAR timings are whatever the recording tool wrote)
+fn scale_duration(duration: Duration, scale: f64) -> Duration {
+    Duration::try_from_secs_f64(duration.as_secs_f64() * scale)
+        .unwrap_or(MAX_PACING)
+        .min(MAX_PACING)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use hyper::body::HttpBody as _;
+    use tokio::runtime::Runtime;
+    use url::Url;
+
+    use super::*;
+    use crate::req_resp::Timings;
+
+    fn request(path: &str) -> Request {
+        let url = format!("http://harplay{}", path);
+        Request {
+            method: "GET".into(),
+            url: Url::parse(&url).unwrap(),
+            original_url: url,
+            origin: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn response(body: &str) -> Response {
+        Response {
+            status_code: 200,
+            headers: Vec::new(),
+            body: Some(body.into()),
+            timings: Timings {
+                wait: Duration::from_millis(100),
+                receive: Duration::from_millis(400),
+            },
+            web_socket_messages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pacing_from_timings() {
+        let request = request("/");
+        assert_eq!(
+            Delivery::new().pacing(&request, &response("")),
+            Pacing::default()
+        );
+        assert_eq!(
+            Delivery::new()
+                .with_latency(0.5)
+                .pacing(&request, &response("")),
+            Pacing {
+                delay: Duration::from_millis(50),
+                spread: Duration::from_millis(200),
+            }
+        );
+    }
+
+    #[test]
+    fn pacing_saturates() {
+        let mut response = response("");
+        response.timings.wait = Duration::MAX;
+
+        assert_eq!(
+            Delivery::new()
+                .with_latency(1000.0)
+                .with_throttle("80:100".parse().unwrap())
+                .pacing(&request("/"), &response),
+            Pacing {
+                delay: MAX_PACING,
+                spread: Duration::from_millis(400_000),
+            }
+        );
+    }
+
+    #[test]
+    fn pacing_from_throttles() {
+        let delivery = Delivery::new()
+            .with_throttle("80:100".parse().unwrap())
+            .with_url_throttle(r"\.mp4$=8:1000".parse().unwrap());
+        let body = "x".repeat(1000);
+
+        assert_eq!(
+            delivery.pacing(&request("/index.html"), &response(&body)),
+            Pacing {
+                delay: Duration::from_millis(100),
+                spread: Duration::from_millis(100),
+            }
+        );
+        assert_eq!(
+            delivery.pacing(&request("/movie.mp4"), &response(&body)),
+            Pacing {
+                delay: Duration::from_millis(1000),
+                spread: Duration::from_secs(1),
+            }
+        );
+
+        // Patterns see the origin requests are for
+        let mut movie = request("/movie.mp4");
+        movie.origin = Some("https://cdn.example.com".into());
+        assert_eq!(
+            Delivery::new()
+                .with_url_throttle(r"^https://cdn\.example\.com/=8:1000".parse().unwrap())
+                .pacing(&movie, &response(&body)),
+            Pacing {
+                delay: Duration::from_millis(1000),
+                spread: Duration::from_secs(1),
+            }
+        );
+
+        // Recorded timings are kept when slower than the throttle
+        assert_eq!(
+            delivery
+                .with_latency(1.0)
+                .pacing(&request("/index.html"), &response(&body)),
+            Pacing {
+                delay: Duration::from_millis(200),
+                spread: Duration::from_millis(400),
+            }
+        );
+    }
+
+    #[test]
+    fn paced_delivery() {
+        let body = "0123456789".repeat(10);
+        let delivery = Delivery::new().with_latency(0.5);
+
+        Runtime::new().unwrap().block_on(async {
+            let start = Instant::now();
+            let http_response = delivery.deliver(&request("/"), response(&body)).await;
+            assert!(start.elapsed() >= Duration::from_millis(50));
+
+            let received = hyper::body::to_bytes(http_response.into_body())
+                .await
+                .unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(200));
+            assert_eq!(&received[..], body.as_bytes());
+        });
+    }
+
+    #[test]
+    fn truncated_delivery() {
+        let body = "0123456789".repeat(10);
+        let delivery = Delivery::new();
+
+        Runtime::new().unwrap().block_on(async {
+            let http_response = delivery
+                .deliver_truncated(&request("/"), response(&body))
+                .await;
+            assert_eq!(
+                http_response.headers().get("content-length").unwrap(),
+                "100"
+            );
+
+            let mut http_body = http_response.into_body();
+            let mut received = Vec::new();
+            let mut errored = false;
+            while let Some(chunk) = http_body.data().await {
+                match chunk {
+                    Ok(chunk) => received.extend_from_slice(&chunk),
+                    Err(_) => errored = true,
+                }
+            }
+
+            assert!(errored);
+            assert_eq!(&received[..], &body.as_bytes()[..50]);
+        });
+    }
+
+    #[test]
+    fn streamed_events() {
+        let body = "data: 1\n\ndata: 2\n\ndata: 3\n\n";
+        let mut response = response(body);
+        response.set_header("Content-Type", "text/event-stream");
+        response.set_header("Content-Length", body.len().to_string());
+
+        let delivery =
+            Delivery::new().with_streaming(Streaming::Interval(Duration::from_millis(100)));
+
+        Runtime::new().unwrap().block_on(async {
+            let start = Instant::now();
+            let http_response = delivery.deliver(&request("/events"), response).await;
+            assert!(http_response.headers().get("content-length").is_none());
+
+            let mut http_body = http_response.into_body();
+            let mut events = Vec::new();
+            while let Some(chunk) = http_body.data().await {
+                events.push((chunk.unwrap(), start.elapsed()));
+            }
+
+            assert_eq!(events.len(), 3);
+            assert_eq!(&events[1].0[..], b"data: 2\n\n");
+            assert!(events[2].1 >= Duration::from_millis(200));
+        });
+    }
+}
@@ -0,0 +1,88 @@
+use crate::req_resp::Response;
+
+pub fn is_event_stream(response: &Response) -> bool {
+    response.header("content-type").is_some_and(|content_type| {
+        content_type
+            .trim_start()
+            .to_ascii_lowercase()
+            .starts_with("text/event-stream")
+    })
+}
+
+pub fn is_chunked(response: &Response) -> bool {
+    response
+        .header("transfer-encoding")
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"))
+}
+
+/// Split a Server-Sent Events body into events, each keeping its trailing blank line
+pub fn split_events(body: &[u8]) -> Vec<Vec<u8>> {
+    split_after(body, |rest| {
+        if rest.starts_with(b"\r\n\r\n") {
+            Some(4)
+        } else if rest.starts_with(b"\n\n") {
+            Some(2)
+        } else {
+            None
+        }
+    })
+}
+
+/// Split a body into lines, each keeping its line ending
+pub fn split_lines(body: &[u8]) -> Vec<Vec<u8>> {
+    split_after(body, |rest| if rest[0] == b'\n' { Some(1) } else { None })
+}
+
+/// Cut `body` after every separator found by `separator`, which gets the rest
+/// of the body and returns the separator length if one starts there
+fn split_after(body: &[u8], separator: impl Fn(&[u8]) -> Option<usize>) -> Vec<Vec<u8>> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut index = 0;
+
+    while index < body.len() {
+        match separator(&body[index..]) {
+            Some(length) => {
+                index += length;
+                pieces.push(body[start..index].to_vec());
+                start = index;
+            }
+            None => index += 1,
+        }
+    }
+
+    if start < body.len() {
+        pieces.push(body[start..].to_vec());
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("", &[] ; "empty")]
+    #[test_case("data: 1\n\n", &["data: 1\n\n"] ; "single event")]
+    #[test_case("data: 1\n\nevent: x\ndata: 2\n\n", &["data: 1\n\n", "event: x\ndata: 2\n\n"] ; "multiple events")]
+    #[test_case("data: 1\r\n\r\ndata: 2", &["data: 1\r\n\r\n", "data: 2"] ; "crlf and unterminated events")]
+    fn event_splitting(body: &str, expected: &[&str]) {
+        let expected: Vec<Vec<u8>> = expected
+            .iter()
+            .map(|piece| piece.as_bytes().to_vec())
+            .collect();
+        assert_eq!(split_events(body.as_bytes()), expected);
+    }
+
+    #[test_case("{}\n{}\n", &["{}\n", "{}\n"] ; "lines")]
+    #[test_case("{}\n\n{}", &["{}\n", "\n", "{}"] ; "blank and unterminated lines")]
+    fn line_splitting(body: &str, expected: &[&str]) {
+        let expected: Vec<Vec<u8>> = expected
+            .iter()
+            .map(|piece| piece.as_bytes().to_vec())
+            .collect();
+        assert_eq!(split_lines(body.as_bytes()), expected);
+    }
+}
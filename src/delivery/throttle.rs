@@ -0,0 +1,127 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use regex::Regex;
+
+use super::MAX_PACING;
+
+/// Network conditions to emulate: a round trip before the first byte, then
+/// a capped download bandwidth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Throttle {
+    /// Download bandwidth, in kilobits per second
+    pub kbps: f64,
+    pub rtt: Duration,
+}
+
+/// Slowest bandwidth, in kilobits per second
+const MIN_KBPS: f64 = 1.0;
+
+impl Throttle {
+    /// Time it takes to download `length` bytes, up to the longest pacing
+    pub fn transfer_time(&self, length: usize) -> Duration {
+        Duration::try_from_secs_f64(length as f64 * 8.0 / (self.kbps * 1000.0))
+            .unwrap_or(MAX_PACING)
+            .min(MAX_PACING)
+    }
+}
+
+impl FromStr for Throttle {
+    type Err = &'static str;
+
+    /// Either a profile name, or a custom `KBPS:RTT_MS` pair
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Same figures as Lighthouse's mobile presets
+        match s {
+            "3g" => Ok(Self {
+                kbps: 700.0,
+                rtt: Duration::from_millis(300),
+            }),
+            "slow-4g" => Ok(Self {
+                kbps: 1638.4,
+                rtt: Duration::from_millis(150),
+            }),
+            custom => {
+                let mut parts = custom.splitn(2, ':');
+                let kbps = parts
+                    .next()
+                    .and_then(|kbps| kbps.parse::<f64>().ok())
+                    .filter(|kbps| kbps.is_finite() && *kbps >= MIN_KBPS);
+                let rtt = parts.next().and_then(|rtt| rtt.parse::<u64>().ok());
+
+                match (kbps, rtt) {
+                    (Some(kbps), Some(rtt)) => Ok(Self {
+                        kbps,
+                        rtt: Duration::from_millis(rtt),
+                    }),
+                    _ => Err("Expected a throttling profile (3g, slow-4g) or KBPS:RTT_MS"),
+                }
+            }
+        }
+    }
+}
+
+/// A throttle applying only to URLs matching a pattern (parsed from `REGEX=PROFILE`)
+#[derive(Debug, Clone)]
+pub struct UrlThrottle {
+    pub pattern: Regex,
+    pub throttle: Throttle,
+}
+
+impl FromStr for UrlThrottle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Profiles never contain `=`, regexes might
+        let split = s
+            .rfind('=')
+            .ok_or_else(|| "Expected REGEX=PROFILE".to_string())?;
+        Ok(Self {
+            pattern: Regex::new(&s[..split]).map_err(|error| error.to_string())?,
+            throttle: s[split + 1..].parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("3g", Some((700.0, 300)))]
+    #[test_case("slow-4g", Some((1638.4, 150)))]
+    #[test_case("256:40", Some((256.0, 40)))]
+    #[test_case("0:40", None)]
+    #[test_case("1e-300:40", None)]
+    #[test_case("256", None)]
+    #[test_case("5g", None)]
+    fn throttle_parsing(input: &str, expected: Option<(f64, u64)>) {
+        assert_eq!(
+            input.parse::<Throttle>().ok(),
+            expected.map(|(kbps, rtt)| Throttle {
+                kbps,
+                rtt: Duration::from_millis(rtt),
+            })
+        );
+    }
+
+    #[test]
+    fn url_throttle_parsing() {
+        let url_throttle: UrlThrottle = r"/media/.*\.mp4|a=b=3g".parse().unwrap();
+        assert_eq!(url_throttle.pattern.as_str(), r"/media/.*\.mp4|a=b");
+        assert_eq!(url_throttle.throttle, "3g".parse().unwrap());
+
+        assert!("no-profile".parse::<UrlThrottle>().is_err());
+        assert!("(=3g".parse::<UrlThrottle>().is_err());
+    }
+
+    #[test]
+    fn transfer_time() {
+        let throttle: Throttle = "8:0".parse().unwrap();
+        assert_eq!(throttle.transfer_time(1000), Duration::from_secs(1));
+
+        let throttle: Throttle = "1:0".parse().unwrap();
+        assert_eq!(throttle.transfer_time(usize::MAX), MAX_PACING);
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{delay_until, Instant};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+use crate::req_resp::{Request, WebSocketData, WebSocketMessage};
+
+/// Appended to the client key before hashing it, as per RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Whether `request` asks for a WebSocket upgrade
+pub fn is_upgrade(request: &Request) -> bool {
+    let has_token = |name: &str, token: &str| {
+        request.header(name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|part| part.trim().eq_ignore_ascii_case(token))
+        })
+    };
+
+    has_token("connection", "upgrade")
+        && has_token("upgrade", "websocket")
+        && request.header("sec-websocket-key").is_some()
+}
+
+/// Value for `Sec-WebSocket-Accept`, answering the client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    base64::encode(sha1.finalize())
+}
+
+impl From<WebSocketData> for Message {
+    fn from(data: WebSocketData) -> Self {
+        match data {
+            WebSocketData::Text(text) => Message::Text(text),
+            WebSocketData::Binary(data) => Message::Binary(data),
+        }
+    }
+}
+
+/// Send the server frames of `messages` following their recorded timings.
+///
+/// When `wait_for_client` is set, recorded client frames hold the replay back
+/// until the client sends its next frame, and later frames are timed from then.
+pub async fn replay<S>(
+    socket: WebSocketStream<S>,
+    messages: Vec<WebSocketMessage>,
+    wait_for_client: bool,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut outgoing, mut incoming) = socket.split();
+    let mut anchor = (Instant::now(), Duration::from_secs(0));
+
+    for message in messages {
+        if message.from_client {
+            if !wait_for_client {
+                continue;
+            }
+
+            let expected = Message::from(message.data);
+            loop {
+                match incoming.next().await {
+                    Some(Ok(received)) if received.is_text() || received.is_binary() => {
+                        if received != expected {
+                            log::debug!("Client sent a different frame than recorded, carrying on");
+                        }
+                        break;
+                    }
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => return,
+                }
+            }
+
+            anchor = (Instant::now(), message.offset);
+            continue;
+        }
+
+        delay_until(anchor.0 + message.offset.saturating_sub(anchor.1)).await;
+        if outgoing.send(message.data.into()).await.is_err() {
+            log::debug!("Client went away while replaying WebSocket frames");
+            return;
+        }
+    }
+
+    // Keep the connection open (answering pings and closes) until the client leaves
+    while let Some(Ok(_)) = incoming.next().await {}
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::runtime::Runtime;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+    use url::Url;
+
+    use super::*;
+    use crate::req_resp::Header;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".into(),
+            url: Url::parse("http://harplay/socket").unwrap(),
+            original_url: "/socket".into(),
+            origin: None,
+            headers: headers
+                .iter()
+                .map(|(name, value)| Header {
+                    name: (*name).into(),
+                    value: (*value).into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn message(from_client: bool, millis: u64, text: &str) -> WebSocketMessage {
+        WebSocketMessage {
+            from_client,
+            offset: Duration::from_millis(millis),
+            data: WebSocketData::Text(text.into()),
+        }
+    }
+
+    #[test_case(&[("Connection", "Upgrade"), ("Upgrade", "websocket"), ("Sec-WebSocket-Key", "x")], true; "upgrade")]
+    #[test_case(&[("Connection", "keep-alive, Upgrade"), ("Upgrade", "WebSocket"), ("Sec-WebSocket-Key", "x")], true; "several tokens")]
+    #[test_case(&[("Connection", "Upgrade"), ("Upgrade", "h2c"), ("Sec-WebSocket-Key", "x")], false; "other protocol")]
+    #[test_case(&[("Connection", "Upgrade"), ("Upgrade", "websocket")], false; "missing key")]
+    #[test_case(&[], false; "plain request")]
+    fn upgrade_detection(headers: &[(&str, &str)], expected: bool) {
+        assert_eq!(is_upgrade(&request(headers)), expected);
+    }
+
+    #[test]
+    fn accept_key_from_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn replays_server_frames() {
+        let messages = vec![
+            message(false, 0, "welcome"),
+            message(true, 50, "subscribe"),
+            message(false, 250, "update"),
+        ];
+
+        Runtime::new().unwrap().block_on(async {
+            let mut listener =
+                TcpListener::from_std(std::net::TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+            let address = listener.local_addr().unwrap();
+
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+                replay(socket, messages, true).await;
+            });
+
+            let stream =
+                TcpStream::from_std(std::net::TcpStream::connect(address).unwrap()).unwrap();
+            let mut client = WebSocketStream::from_raw_socket(stream, Role::Client, None).await;
+
+            let first = client.next().await.unwrap().unwrap();
+            assert_eq!(first, Message::Text("welcome".into()));
+
+            let start = Instant::now();
+            client
+                .send(Message::Text("subscribe".into()))
+                .await
+                .unwrap();
+
+            let second = client.next().await.unwrap().unwrap();
+            assert_eq!(second, Message::Text("update".into()));
+            // Timed from the client frame, not from the start of the connection
+            assert!(start.elapsed() >= Duration::from_millis(200));
+        });
+    }
+}
@@ -13,6 +13,45 @@ pub enum AppError {
     RequestLookup,
     #[snafu(display("Response not found"))]
     ResponseLookup,
+    #[snafu(display("Error reading stored response body"))]
+    BodyStorage,
+    #[snafu(display("Connection reset by fault injection"))]
+    InjectedReset,
+    #[snafu(display("Error forwarding the request upstream: {}", source))]
+    Forwarding { source: hyper::Error },
+    #[snafu(display("Invalid upstream URL: {}", source))]
+    UpstreamUrl { source: http::uri::InvalidUri },
+    #[snafu(display("Error saving the recorded HAR: {}", source))]
+    Saving { source: crate::har::HarError },
+    #[snafu(display("Error reading {:?}: {}", path, source))]
+    TlsFile {
+        source: std::io::Error,
+        path: std::path::PathBuf,
+    },
+    #[snafu(display("No PEM {} found in {:?}", what, path))]
+    TlsPem {
+        what: &'static str,
+        path: std::path::PathBuf,
+    },
+    #[snafu(display("Error generating a self-signed certificate: {}", source))]
+    SelfSigned { source: rcgen::RcgenError },
+    #[snafu(display("Invalid CA key in {:?}: {}", path, source))]
+    CaKey {
+        source: rcgen::RcgenError,
+        path: std::path::PathBuf,
+    },
+    #[snafu(display(
+        "{:?} can be read by other users, make it private first (chmod 600)",
+        path
+    ))]
+    CaKeyExposed { path: std::path::PathBuf },
+    #[snafu(display("Error making up a certificate for {}: {}", host, source))]
+    Signing {
+        source: rcgen::RcgenError,
+        host: String,
+    },
+    #[snafu(display("Invalid TLS certificate or key: {}", source))]
+    TlsConfig { source: rustls::TLSError },
 }
 
 impl From<ResponderError> for AppError {
@@ -20,6 +59,7 @@ impl From<ResponderError> for AppError {
         match error {
             ResponderError::RequestNotFound => Self::RequestLookup,
             ResponderError::ResponseNotFound => Self::ResponseLookup,
+            ResponderError::ReadingBody => Self::BodyStorage,
         }
     }
 }
@@ -50,6 +90,10 @@ mod tests {
             AppError::from(ResponderError::ResponseNotFound),
             AppError::ResponseLookup
         );
+        assert_matches!(
+            AppError::from(ResponderError::ReadingBody),
+            AppError::BodyStorage
+        );
     }
 
     #[test]
@@ -70,6 +114,8 @@ mod tests {
             HttpResponse::from(AppError::DatabaseLock),
             HttpResponse::from(AppError::RequestLookup),
             HttpResponse::from(AppError::ResponseLookup),
+            HttpResponse::from(AppError::BodyStorage),
+            HttpResponse::from(AppError::InjectedReset),
         ];
 
         for resp in responses {
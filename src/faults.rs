@@ -0,0 +1,286 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use regex::Regex;
+
+use crate::req_resp::Request;
+
+/// What can go wrong with a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum FaultKind {
+    /// Answer with this status code instead of the recorded response
+    Status(u16),
+    /// Close the connection without answering
+    Reset,
+    /// Stop sending the body halfway through
+    Truncate,
+    /// Hold the request back for a random time in this range
+    Delay(Duration, Duration),
+    /// Serve a different recorded response for the same request
+    WrongVariant,
+}
+
+impl FromStr for FaultKind {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().unwrap_or_default(), parts.next()) {
+            ("status", Some(code)) => code
+                .parse::<u16>()
+                .ok()
+                .filter(|code| (100..1000).contains(code))
+                .map(Self::Status)
+                .ok_or("Invalid fault status code"),
+            ("reset", None) => Ok(Self::Reset),
+            ("truncate", None) => Ok(Self::Truncate),
+            ("delay", Some(millis)) => {
+                let mut bounds = millis.splitn(2, '-').map(str::parse::<u64>);
+                match (bounds.next(), bounds.next()) {
+                    (Some(Ok(min)), None) => Ok(Self::Delay(
+                        Duration::from_millis(min),
+                        Duration::from_millis(min),
+                    )),
+                    (Some(Ok(min)), Some(Ok(max))) if min <= max => Ok(Self::Delay(
+                        Duration::from_millis(min),
+                        Duration::from_millis(max),
+                    )),
+                    _ => Err("Invalid fault delay, expected MS or MIN_MS-MAX_MS"),
+                }
+            }
+            ("wrong-variant", None) => Ok(Self::WrongVariant),
+            _ => Err("Unrecognized fault, expected one of status:CODE, reset, truncate, delay:MIN_MS-MAX_MS or wrong-variant"),
+        }
+    }
+}
+
+/// A fault happening to a share of the requests, optionally only to matching
+/// URLs (parsed from `[REGEX=]KIND[@PERCENT%]`)
+#[derive(Debug, Clone)]
+pub struct FaultRule {
+    pub pattern: Option<Regex>,
+    pub kind: FaultKind,
+    /// Between 0 and 1
+    pub probability: f64,
+}
+
+impl FromStr for FaultRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Neither fault kinds nor percentages contain `=` or `@`, regexes might
+        let (rest, probability) = match s.rfind('@') {
+            Some(split) => (&s[..split], parse_percentage(&s[split + 1..])?),
+            None => (s, 1.0),
+        };
+
+        let (pattern, kind) = match rest.rfind('=') {
+            Some(split) => (
+                Some(Regex::new(&rest[..split]).map_err(|error| error.to_string())?),
+                &rest[split + 1..],
+            ),
+            None => (None, rest),
+        };
+
+        Ok(Self {
+            pattern,
+            kind: kind.parse()?,
+            probability,
+        })
+    }
+}
+
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    s.trim_end_matches('%')
+        .parse::<f64>()
+        .ok()
+        .filter(|percentage| (0.0..=100.0).contains(percentage))
+        .map(|percentage| percentage / 100.0)
+        .ok_or_else(|| format!("Invalid fault percentage {:?}", s))
+}
+
+/// A fault picked for a specific request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    Status(u16),
+    Reset,
+    Truncate,
+    Delay(Duration),
+    WrongVariant,
+}
+
+/// Decides which requests go wrong, and how
+#[derive(Debug)]
+pub struct FaultInjector {
+    rules: Vec<FaultRule>,
+    rng: StdRng,
+}
+
+impl FaultInjector {
+    /// Same `seed`, same rules and same requests means the same faults
+    pub fn new(rules: Vec<FaultRule>, seed: Option<u64>) -> Self {
+        Self {
+            rules,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Pick a fault for `request`; the first matching rule that hits wins
+    pub fn roll(&mut self, request: &Request) -> Option<Fault> {
+        let url = request.public_url();
+
+        for rule in self.rules.iter() {
+            if !rule
+                .pattern
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(&url))
+            {
+                continue;
+            }
+            if !self.rng.gen_bool(rule.probability) {
+                continue;
+            }
+
+            return Some(match rule.kind {
+                FaultKind::Status(code) => Fault::Status(code),
+                FaultKind::Reset => Fault::Reset,
+                FaultKind::Truncate => Fault::Truncate,
+                // Whole milliseconds, as given, up to and including the maximum
+                FaultKind::Delay(min, max) => Fault::Delay(Duration::from_millis(
+                    self.rng
+                        .gen_range(min.as_millis() as u64, max.as_millis() as u64 + 1),
+                )),
+                FaultKind::WrongVariant => Fault::WrongVariant,
+            });
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+    use url::Url;
+
+    use super::*;
+
+    fn request(path: &str) -> Request {
+        let url = format!("http://harplay{}", path);
+        Request {
+            method: "GET".into(),
+            url: Url::parse(&url).unwrap(),
+            original_url: url,
+            origin: None,
+            headers: Vec::new(),
+        }
+    }
+
+    #[test_case("status:503", Ok(FaultKind::Status(503)))]
+    #[test_case("reset", Ok(FaultKind::Reset))]
+    #[test_case("truncate", Ok(FaultKind::Truncate))]
+    #[test_case(
+        "delay:100",
+        Ok(FaultKind::Delay(Duration::from_millis(100), Duration::from_millis(100)))
+    )]
+    #[test_case(
+        "delay:100-500",
+        Ok(FaultKind::Delay(Duration::from_millis(100), Duration::from_millis(500)))
+    )]
+    #[test_case("wrong-variant", Ok(FaultKind::WrongVariant))]
+    #[test_case("status:5000", Err(()))]
+    #[test_case("delay:500-100", Err(()))]
+    #[test_case("reset:1", Err(()))]
+    #[test_case("explode", Err(()))]
+    fn fault_kind_parsing(input: &str, expected: Result<FaultKind, ()>) {
+        assert_eq!(input.parse::<FaultKind>().map_err(|_| ()), expected);
+    }
+
+    #[test]
+    fn fault_rule_parsing() {
+        let rule: FaultRule = "reset".parse().unwrap();
+        assert!(rule.pattern.is_none());
+        assert_eq!(rule.kind, FaultKind::Reset);
+        assert_eq!(rule.probability, 1.0);
+
+        let rule: FaultRule = "/api/(a|b)=c=status:502@12.5%".parse().unwrap();
+        assert_eq!(rule.pattern.unwrap().as_str(), "/api/(a|b)=c");
+        assert_eq!(rule.kind, FaultKind::Status(502));
+        assert_eq!(rule.probability, 0.125);
+
+        assert!("reset@120%".parse::<FaultRule>().is_err());
+        assert!("(=reset".parse::<FaultRule>().is_err());
+    }
+
+    #[test]
+    fn rules_are_scoped() {
+        let mut injector = FaultInjector::new(
+            vec![
+                "/api/=status:503".parse().unwrap(),
+                "/static/=reset@0%".parse().unwrap(),
+            ],
+            None,
+        );
+
+        assert_eq!(
+            injector.roll(&request("/api/users")),
+            Some(Fault::Status(503))
+        );
+        assert_eq!(injector.roll(&request("/static/app.js")), None);
+        assert_eq!(injector.roll(&request("/index.html")), None);
+    }
+
+    #[test]
+    fn rules_see_origins() {
+        let mut injector = FaultInjector::new(
+            vec![r"^https://api\.example\.com/=reset".parse().unwrap()],
+            None,
+        );
+        let mut request = request("/users");
+
+        assert_eq!(injector.roll(&request), None);
+        request.origin = Some("https://api.example.com".into());
+        assert_eq!(injector.roll(&request), Some(Fault::Reset));
+    }
+
+    #[test]
+    fn delays_include_their_bounds() {
+        let mut injector = FaultInjector::new(vec!["delay:1-2".parse().unwrap()], Some(42));
+        let delays: Vec<_> = (0..50).map(|_| injector.roll(&request("/"))).collect();
+
+        for millis in 1..=2 {
+            assert!(delays.contains(&Some(Fault::Delay(Duration::from_millis(millis)))));
+        }
+        assert!(delays.iter().all(|delay| match delay {
+            Some(Fault::Delay(delay)) => (1..=2).contains(&delay.as_millis()),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn seeded_injectors_repeat_themselves() {
+        let rules: Vec<FaultRule> = vec![
+            "truncate@30%".parse().unwrap(),
+            "delay:10-1000@50%".parse().unwrap(),
+        ];
+        let request = request("/");
+
+        let mut first = FaultInjector::new(rules.clone(), Some(42));
+        let mut second = FaultInjector::new(rules, Some(42));
+
+        let first_faults: Vec<_> = (0..50).map(|_| first.roll(&request)).collect();
+        let second_faults: Vec<_> = (0..50).map(|_| second.roll(&request)).collect();
+
+        assert_eq!(first_faults, second_faults);
+        assert!(first_faults.iter().any(Option::is_none));
+        assert!(first_faults.iter().any(Option::is_some));
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flate2::bufread::{DeflateDecoder, MultiGzDecoder};
+use flate2::write::GzEncoder;
+use tempfile::NamedTempFile;
+use zip::{result::ZipError, CompressionMethod, ZipArchive};
+
+use super::errors::*;
+
+/// How a HAR file is compressed, told apart by its first bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Zip,
+}
+
+impl Compression {
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            Self::Zip
+        } else {
+            Self::None
+        }
+    }
+
+    /// How a file at `path` should be compressed, told by its extension
+    /// (`.gz`, `.zst` or `.zip`)
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            Some("zip") => Self::Zip,
+            _ => Self::None,
+        }
+    }
+
+    /// Fail for compressions files can't be written with, zip archives
+    pub fn check_writable(self) -> Result<(), HarError> {
+        if self == Self::Zip {
+            return Err(ZipError::UnsupportedArchive(
+                "Writing zip archives is not supported",
+            ))
+            .context(Archive);
+        }
+        Ok(())
+    }
+}
+
+/// Open the HAR at `path` for reading, transparently decompressing gzip and
+/// zstd files. Out of zip archives, the entry named `zip_entry` is read, or
+/// the first `.har` one.
+pub fn open<P: AsRef<Path>>(path: P, zip_entry: Option<&str>) -> Result<Box<dyn Read>, HarError> {
+    let mut read = BufReader::new(File::open(path).context(Opening)?);
+    let compression = Compression::detect(read.fill_buf().context(Opening)?);
+
+    Ok(match compression {
+        Compression::None => Box::new(read),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(read))),
+        Compression::Zstd => Box::new(BufReader::new(
+            zstd::Decoder::with_buffer(read).context(Decompressing)?,
+        )),
+        Compression::Zip => open_zip_entry(read.into_inner(), zip_entry)?,
+    })
+}
+
+/// Create (or replace) the file at `path` and have `write` fill it in,
+/// compressed with `compression`. It's written to a temporary file next to it
+/// first, so a failure midway leaves whatever was there before. Zip archives
+/// can't be written.
+pub fn create<P, F>(path: P, compression: Compression, write: F) -> Result<(), HarError>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<(), HarError>,
+{
+    compression.check_writable()?;
+
+    let path = path.as_ref();
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut file = BufWriter::new(NamedTempFile::new_in(directory).context(Creating)?);
+    match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(&mut file, flate2::Compression::default());
+            write(&mut encoder)?;
+            encoder.finish().context(Compressing)?;
+        }
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(&mut file, 0).context(Compressing)?;
+            write(&mut encoder)?;
+            encoder.finish().context(Compressing)?;
+        }
+        _ => write(&mut file)?,
+    }
+
+    let file = file
+        .into_inner()
+        .map_err(|error| error.into_error())
+        .context(Creating)?;
+    file.as_file().sync_all().context(Creating)?;
+    file.persist(path)
+        .map_err(|error| error.error)
+        .context(Creating)?;
+    Ok(())
+}
+
+fn open_zip_entry<R: Read + Seek + 'static>(
+    read: R,
+    name: Option<&str>,
+) -> Result<Box<dyn Read>, HarError> {
+    let mut archive = ZipArchive::new(read).context(Archive)?;
+
+    let mut found = None;
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index).context(Archive)?;
+        let is_match = match name {
+            Some(name) => file.name() == name,
+            None => !file.is_dir() && file.name().to_ascii_lowercase().ends_with(".har"),
+        };
+        if is_match {
+            found = Some((
+                file.data_start(),
+                file.compressed_size(),
+                file.compression(),
+            ));
+            break;
+        }
+    }
+
+    let (start, size, compression) = found.context(ArchiveEntry {
+        name: name.unwrap_or("*.har"),
+    })?;
+
+    // Read the entry straight out of the archive, instead of through a
+    // `ZipFile` borrowing it, so it can be handed over as any other reader
+    let mut read = archive.into_inner();
+    read.seek(SeekFrom::Start(start)).context(Decompressing)?;
+    let data = BufReader::new(read.take(size));
+
+    match compression {
+        CompressionMethod::Stored => Ok(Box::new(data)),
+        CompressionMethod::Deflated => Ok(Box::new(BufReader::new(DeflateDecoder::new(data)))),
+        _ => Err(ZipError::UnsupportedArchive(
+            "Compression method not supported",
+        ))
+        .context(Archive),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use assert_matches::assert_matches;
+    use flate2::{write::GzEncoder, Compression as GzLevel};
+    use tempfile::NamedTempFile;
+    use test_case::test_case;
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    const HAR: &str =
+        r#"{"log":{"creator":{"name":"Creator?","version":"0.1"},"pages":[],"entries":[]}}"#;
+
+    fn read_all(path: &Path, zip_entry: Option<&str>) -> Result<String, HarError> {
+        let mut contents = String::new();
+        open(path, zip_entry)?
+            .read_to_string(&mut contents)
+            .context(Decompressing)?;
+        Ok(contents)
+    }
+
+    fn zip_file(files: &[(&str, &str)], method: CompressionMethod) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut zip = ZipWriter::new(file.reopen().unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, FileOptions::default().compression_method(method))
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        file
+    }
+
+    #[test_case(&[0x1f, 0x8b, 0x08], Compression::Gzip; "gzip")]
+    #[test_case(&[0x28, 0xb5, 0x2f, 0xfd, 0x00], Compression::Zstd; "zstd")]
+    #[test_case(b"PK\x03\x04", Compression::Zip; "zip")]
+    #[test_case(b"{\"log\"", Compression::None; "json")]
+    #[test_case(b"", Compression::None; "empty")]
+    fn compression_detection(magic: &[u8], expected: Compression) {
+        assert_eq!(Compression::detect(magic), expected);
+    }
+
+    #[test_case("a.har", Compression::None; "plain")]
+    #[test_case("a.har.gz", Compression::Gzip; "gzip")]
+    #[test_case("dir.gz/a.HAR.ZST", Compression::Zstd; "zstd")]
+    #[test_case("a.zip", Compression::Zip; "zip")]
+    fn compression_by_extension(path: &str, expected: Compression) {
+        assert_eq!(Compression::from_extension(path), expected);
+    }
+
+    #[test]
+    fn reads_plain_files() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(HAR.as_bytes()).unwrap();
+        assert_eq!(read_all(file.path(), None).unwrap(), HAR);
+    }
+
+    #[test]
+    fn reads_gzip_files() {
+        let mut file = NamedTempFile::new().unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+        encoder.write_all(HAR.as_bytes()).unwrap();
+        file.write_all(&encoder.finish().unwrap()).unwrap();
+
+        assert_eq!(read_all(file.path(), None).unwrap(), HAR);
+    }
+
+    #[test]
+    fn reads_zstd_files() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&zstd::encode_all(HAR.as_bytes(), 0).unwrap())
+            .unwrap();
+
+        assert_eq!(read_all(file.path(), None).unwrap(), HAR);
+    }
+
+    #[test_case(CompressionMethod::Stored; "stored")]
+    #[test_case(CompressionMethod::Deflated; "deflated")]
+    fn reads_zip_entries(method: CompressionMethod) {
+        let file = zip_file(
+            &[
+                ("README.txt", "Not a HAR"),
+                ("sessions/first.har", HAR),
+                ("second.HAR", "{}"),
+            ],
+            method,
+        );
+
+        assert_eq!(read_all(file.path(), None).unwrap(), HAR);
+        assert_eq!(read_all(file.path(), Some("second.HAR")).unwrap(), "{}");
+        assert_matches!(
+            read_all(file.path(), Some("third.har")),
+            Err(HarError::ArchiveEntry { .. })
+        );
+    }
+
+    #[test_case(Compression::None; "plain")]
+    #[test_case(Compression::Gzip; "gzip")]
+    #[test_case(Compression::Zstd; "zstd")]
+    fn writes_what_it_reads(compression: Compression) {
+        let file = NamedTempFile::new().unwrap();
+        create(file.path(), compression, |write| {
+            write.write_all(HAR.as_bytes()).context(Creating)
+        })
+        .unwrap();
+
+        let mut magic = [0; 4];
+        File::open(file.path())
+            .unwrap()
+            .read_exact(&mut magic)
+            .unwrap();
+        assert_eq!(Compression::detect(&magic), compression);
+        assert_eq!(read_all(file.path(), None).unwrap(), HAR);
+    }
+
+    #[test]
+    fn keeps_what_was_there_when_writing_fails() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("a.har");
+        std::fs::write(&path, HAR).unwrap();
+
+        let failed = create(&path, Compression::None, |write| {
+            write.write_all(b"{\"log\":").context(Creating)?;
+            Err(std::io::Error::from(std::io::ErrorKind::Other)).context(Creating)
+        });
+        assert_matches!(failed, Err(HarError::Creating { .. }));
+        assert_eq!(read_all(&path, None).unwrap(), HAR);
+        // No temporary file left behind
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn complains_about_zips_without_hars() {
+        let file = zip_file(&[("README.txt", "Not a HAR")], CompressionMethod::Stored);
+        assert_matches!(
+            read_all(file.path(), None),
+            Err(HarError::ArchiveEntry { .. })
+        );
+    }
+}
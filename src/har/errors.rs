@@ -1,4 +1,7 @@
 use std::io::Error as IoError;
+use std::path::PathBuf;
+
+use zip::result::ZipError;
 
 pub use snafu::{ensure, Backtrace, ErrorCompat, OptionExt, ResultExt, Snafu};
 
@@ -8,6 +11,69 @@ pub enum HarError {
     #[snafu(display("Reading error: {}", source))]
     Reading { source: serde_json::Error },
 
+    #[snafu(display("Invalid {}: {}", location, source))]
+    InvalidEntry {
+        source: serde_json::Error,
+        location: String,
+    },
+
+    #[snafu(display("Writing error: {}", source))]
+    Writing { source: serde_json::Error },
+
     #[snafu(display("File opening error: {}", source))]
     Opening { source: IoError },
+
+    #[snafu(display("File creation error: {}", source))]
+    Creating { source: IoError },
+
+    #[snafu(display("Error listing {}: {}", path.display(), source))]
+    Listing { source: IoError, path: PathBuf },
+
+    #[snafu(display("Decompressing error: {}", source))]
+    Decompressing { source: IoError },
+
+    #[snafu(display("Compressing error: {}", source))]
+    Compressing { source: IoError },
+
+    #[snafu(display("Archive error: {}", source))]
+    Archive { source: ZipError },
+
+    #[snafu(display("No {} entry in the archive", name))]
+    ArchiveEntry { name: String },
+
+    #[snafu(display("Invalid date {:?}: {}", date, source))]
+    InvalidDate {
+        source: chrono::ParseError,
+        date: String,
+    },
+
+    #[snafu(display("Invalid method {:?}: {}", method, source))]
+    InvalidMethod {
+        source: http::method::InvalidMethod,
+        method: String,
+    },
+
+    #[snafu(display("Invalid URL {:?}: {}", url, source))]
+    InvalidUrl {
+        source: url::ParseError,
+        url: String,
+    },
+
+    #[snafu(display("Invalid HTTP version {:?}", version))]
+    InvalidHttpVersion { version: String },
+
+    #[snafu(display("Invalid status code {}", status))]
+    InvalidStatus { status: i64 },
+
+    #[snafu(display("Invalid header name {:?}: {}", name, source))]
+    InvalidHeaderName {
+        source: http::header::InvalidHeaderName,
+        name: String,
+    },
+
+    #[snafu(display("Invalid value for header {:?}: {}", name, source))]
+    InvalidHeaderValue {
+        source: http::header::InvalidHeaderValue,
+        name: String,
+    },
 }
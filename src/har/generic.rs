@@ -1,14 +1,22 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Log {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<String>,
     pub creator: Creator,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub browser: Option<Creator>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub pages: Option<Vec<Pages>>,
     pub entries: Vec<Entries>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -17,6 +25,8 @@ pub struct Creator {
     pub version: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -24,28 +34,37 @@ pub struct Pages {
     #[serde(rename = "startedDateTime")]
     pub started_date_time: String,
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
     #[serde(rename = "pageTimings")]
     pub page_timings: PageTimings,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct PageTimings {
     #[serde(rename = "onContentLoad")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub on_content_load: Option<i64>,
     #[serde(rename = "onLoad")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub on_load: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Entries {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub pageref: Option<String>,
     #[serde(rename = "startedDateTime")]
     pub started_date_time: String,
+    #[serde(serialize_with = "integral")]
     pub time: f64,
     pub request: Request,
     pub response: Response,
@@ -58,6 +77,27 @@ pub struct Entries {
     pub connection: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    /// Chrome extension: frames of WebSocket connections
+    #[serde(rename = "_webSocketMessages")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_socket_messages: Option<Vec<WebSocketMessage>>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
+pub struct WebSocketMessage {
+    /// Either `send` (client to server) or `receive` (server to client)
+    #[serde(rename = "type")]
+    pub message_type: String,
+    /// Seconds since the epoch
+    #[serde(serialize_with = "integral")]
+    pub time: f64,
+    pub opcode: i64,
+    /// Base64-encoded for binary frames
+    pub data: String,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -81,7 +121,10 @@ pub struct Request {
     pub comment: Option<String>,
     #[serde(rename = "headersCompression")]
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub headers_compression: Option<f64>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -90,20 +133,29 @@ pub struct Headers {
     pub value: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Cookies {
     pub name: String,
     pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub expires: Option<String>,
     #[serde(rename = "httpOnly")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub http_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub secure: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -112,13 +164,15 @@ pub struct QueryString {
     pub value: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct PostData {
     #[serde(rename = "mimeType")]
     pub mime_type: String,
-    /// Either text or params but not both : TODO turn into an untagged enum
+    /// Either text or params but not both, see `typed::PostData`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -127,11 +181,14 @@ pub struct PostData {
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encoding: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Params {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub value: Option<String>,
     #[serde(rename = "fileName")]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -143,6 +200,8 @@ pub struct Params {
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encoding: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -168,15 +227,21 @@ pub struct Response {
     pub comment: Option<String>,
     #[serde(rename = "headersCompression")]
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub headers_compression: Option<f64>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub size: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub compression: Option<i64>,
     #[serde(rename = "mimeType")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub mime_type: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub text: Option<String>,
@@ -184,6 +249,8 @@ pub struct Content {
     pub encoding: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
@@ -194,10 +261,13 @@ pub struct Cache {
     #[serde(rename = "afterRequest")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub after_request: Option<CacheEntity>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct CacheEntity {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub expires: Option<String>,
     #[serde(rename = "lastAccess")]
     pub last_access: String,
@@ -207,21 +277,99 @@ pub struct CacheEntity {
     pub hit_count: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
 pub struct Timings {
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub blocked: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dns: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub connect: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub send: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub wait: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "optional_integral")]
     pub receive: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ssl: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Extensions,
+}
+
+/// Vendor-specific fields, which are prefixed with an underscore (Chrome's
+/// `_initiator`, `_resourceType`...), kept as they are so they survive a
+/// round-trip. Other unknown fields are dropped.
+#[derive(Clone, Debug, Serialize, PartialEq, Default)]
+#[serde(transparent)]
+pub struct Extensions(pub BTreeMap<String, Value>);
+
+impl<'de> Deserialize<'de> for Extensions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = BTreeMap::<String, Value>::deserialize(deserializer)?;
+        Ok(Self(
+            fields
+                .into_iter()
+                .filter(|(name, _)| name.starts_with('_'))
+                .collect(),
+        ))
+    }
+}
+
+impl Extensions {
+    /// The field called `name`, underscore included
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(Value::as_str)
+    }
+}
+
+/// Write numbers like JavaScript, which exports most HARs, does: integral ones
+/// without a fraction (`1` rather than `1.0`), so that they round-trip
+fn integral<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    // Past 2^53 not every integer is a float anymore
+    if value.fract() == 0.0 && value.abs() < 9_007_199_254_740_992.0 {
+        serializer.serialize_i64(*value as i64)
+    } else {
+        serializer.serialize_f64(*value)
+    }
+}
+
+fn optional_integral<S: Serializer>(value: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => integral(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl Entries {
+    /// What the browser loaded this for (`document`, `script`, `xhr`...), as
+    /// recorded by Chrome
+    pub fn resource_type(&self) -> Option<&str> {
+        self.extensions.get_str("_resourceType")
+    }
+
+    /// Which browser cache served the response (`memory` or `disk`), if any
+    pub fn browser_cache(&self) -> Option<&str> {
+        self.extensions.get_str("_fromCache")
+    }
+
+    /// Whether the response came out of a browser cache instead of the network
+    pub fn is_from_cache(&self) -> bool {
+        self.browser_cache().is_some()
+    }
 }
@@ -0,0 +1,382 @@
+//! Working around the many ways exporters get HARs slightly wrong: missing
+//! fields get a default, numbers and strings are coerced into what the model
+//! expects, and what can't be made sense of is dropped. Every repair is
+//! reported, so nothing is papered over silently.
+//!
+//! Repairs go by where serde failed and what the model expects there, never
+//! by the wording of its error, which is only passed on for display.
+
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use serde_path_to_error::{Path, Segment};
+
+use super::{Entries, Har};
+
+/// Give up on a value after this many repairs, in case they go around in circles
+const MAX_REPAIRS: usize = 64;
+
+/// A problem found in a HAR, either repaired or the reason an entry was skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Index of the entry the problem is in, if any
+    pub entry: Option<usize>,
+    /// JSON path to the problem, within the entry if there is one
+    pub path: String,
+    pub message: String,
+    /// What was done about it, if anything
+    pub repair: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&location(self.entry, &self.path))?;
+        write!(formatter, ": {}", self.message)?;
+        if let Some(repair) = &self.repair {
+            write!(formatter, "; {}", repair)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where in a HAR `path` is, as `entries[3].response.redirectURL`
+pub fn location(entry: Option<usize>, path: &str) -> String {
+    match (entry, path) {
+        (Some(entry), ".") => format!("entries[{}]", entry),
+        (Some(entry), path) => format!("entries[{}].{}", entry, path),
+        (None, path) => path.to_owned(),
+    }
+}
+
+/// Something `deserialize` knows the shape of
+pub trait Repairable: DeserializeOwned {
+    /// What it's called in `fields`: the key it's found under in a HAR
+    const KIND: &'static str;
+}
+
+impl Repairable for Har {
+    const KIND: &'static str = "har";
+}
+
+impl Repairable for Entries {
+    const KIND: &'static str = "entries";
+}
+
+/// Deserialize `value`, repairing it along the way and reporting each repair
+/// to `on_diagnostic`. Fails with the first problem that can't be repaired.
+pub fn deserialize<T: Repairable>(
+    mut value: Value,
+    entry: Option<usize>,
+    on_diagnostic: &mut dyn FnMut(Diagnostic),
+) -> Result<T, Diagnostic> {
+    for _ in 0..MAX_REPAIRS {
+        let error = match serde_path_to_error::deserialize::<_, T>(&value) {
+            Ok(deserialized) => return Ok(deserialized),
+            Err(error) => error,
+        };
+
+        let path = error.path().to_string();
+        let message = error.inner().to_string();
+        match repair(&mut value, error.path(), T::KIND) {
+            repair @ Some(_) => on_diagnostic(Diagnostic {
+                entry,
+                path,
+                message,
+                repair,
+            }),
+            None => {
+                return Err(Diagnostic {
+                    entry,
+                    path,
+                    message,
+                    repair: None,
+                })
+            }
+        }
+    }
+
+    Err(Diagnostic {
+        entry,
+        path: ".".into(),
+        message: "too many problems".into(),
+        repair: None,
+    })
+}
+
+/// Fix whatever is wrong at `path`, going by what's there and what the HAR
+/// model expects there rather than by what serde said. Returns what was done
+/// about it.
+fn repair(value: &mut Value, path: &Path, root: &str) -> Option<String> {
+    let segments: Vec<&Segment> = path.iter().collect();
+
+    // An object where one is expected can only be missing a field
+    let kind = match segments.split_last() {
+        None => Some(root),
+        Some((Segment::Seq { .. }, parent)) => parent.last().and_then(|segment| key(segment)),
+        Some((Segment::Map { key }, _)) => {
+            Some(key.as_str()).filter(|key| shape(key) == Some(Shape::Object))
+        }
+        Some(_) => None,
+    };
+    if let (Some(kind), Some(Value::Object(object))) = (kind, lookup(value, &segments)) {
+        let missing = fields(kind)
+            .iter()
+            .find(|field| !object.contains_key(**field))?;
+        let default = default_for(missing)?;
+        let repair = format!("defaulted `{}` to {}", missing, default);
+        object.insert((*missing).to_owned(), default);
+        return Some(repair);
+    }
+
+    let (last, parent) = segments.split_last()?;
+    let field = key(last)?;
+    let target = lookup(value, &segments)?;
+    match shape(field)
+        .and_then(|shape| coerce(target, shape))
+        .or_else(|| default_for(field))
+        .filter(|replacement| *replacement != *target)
+    {
+        Some(replacement) => {
+            let repair = format!("replaced with {}", replacement);
+            *target = replacement;
+            Some(repair)
+        }
+        // Optional fields are better off missing than wrong, required ones
+        // come back as missing
+        None => {
+            lookup(value, parent)?.as_object_mut()?.remove(field);
+            Some("dropped".into())
+        }
+    }
+}
+
+fn key(segment: &Segment) -> Option<&str> {
+    match segment {
+        Segment::Map { key } => Some(key.as_str()),
+        _ => None,
+    }
+}
+
+fn lookup<'a>(value: &'a mut Value, segments: &[&Segment]) -> Option<&'a mut Value> {
+    segments
+        .iter()
+        .try_fold(value, |value, segment| match segment {
+            Segment::Seq { index } => value.get_mut(index),
+            Segment::Map { key } => value.get_mut(key.as_str()),
+            _ => None,
+        })
+}
+
+/// What the HAR model expects a field to hold
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    Integer,
+    Float,
+    Text,
+    Object,
+}
+
+fn shape(field: &str) -> Option<Shape> {
+    Some(match field {
+        "status" | "headersSize" | "bodySize" | "hitCount" | "dns" | "ssl" | "opcode"
+        | "compression" | "onContentLoad" | "onLoad" => Shape::Integer,
+        "time" | "blocked" | "connect" | "send" | "wait" | "receive" | "size"
+        | "headersCompression" => Shape::Float,
+        "version" | "name" | "value" | "startedDateTime" | "id" | "title" | "pageref"
+        | "serverIPAddress" | "connection" | "comment" | "type" | "data" | "method" | "url"
+        | "httpVersion" | "statusText" | "redirectURL" | "mimeType" | "text" | "encoding"
+        | "path" | "domain" | "expires" | "fileName" | "contentType" | "lastAccess" | "eTag"
+        | "_charlesStatus" => Shape::Text,
+        "log" | "creator" | "browser" | "pageTimings" | "request" | "response" | "cache"
+        | "timings" | "postData" | "content" | "beforeRequest" | "afterRequest" => Shape::Object,
+        _ => return None,
+    })
+}
+
+/// The required fields of each kind of object, in the order the model
+/// declares them (which is the order serde complains about them in)
+fn fields(kind: &str) -> &'static [&'static str] {
+    match kind {
+        "har" => &["log"],
+        "log" => &["creator", "entries"],
+        "creator" | "browser" => &["name", "version"],
+        "pages" => &["startedDateTime", "id", "pageTimings"],
+        "entries" => &[
+            "startedDateTime",
+            "time",
+            "request",
+            "response",
+            "cache",
+            "timings",
+        ],
+        "_webSocketMessages" => &["type", "time", "opcode", "data"],
+        "request" => &[
+            "method",
+            "url",
+            "httpVersion",
+            "cookies",
+            "headers",
+            "queryString",
+            "headersSize",
+            "bodySize",
+        ],
+        "response" => &[
+            "status",
+            "statusText",
+            "httpVersion",
+            "cookies",
+            "headers",
+            "content",
+            "redirectURL",
+            "headersSize",
+            "bodySize",
+        ],
+        "headers" | "cookies" | "queryString" => &["name", "value"],
+        "postData" => &["mimeType"],
+        "params" => &["name"],
+        "beforeRequest" | "afterRequest" => &["lastAccess", "eTag", "hitCount"],
+        _ => &[],
+    }
+}
+
+/// Turn `value` into `shape`, if it makes sense
+fn coerce(value: &Value, shape: Shape) -> Option<Value> {
+    let number = match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+    .filter(|number| number.is_finite());
+
+    match shape {
+        Shape::Integer => number.map(|number| json!(number.round() as i64)),
+        Shape::Float => number.map(|number| json!(number)),
+        Shape::Text => match value {
+            Value::Number(number) => Some(Value::String(number.to_string())),
+            Value::Bool(boolean) => Some(Value::String(boolean.to_string())),
+            _ => None,
+        },
+        Shape::Object => None,
+    }
+}
+
+/// What a missing (or hopeless) required field is taken to be, following the
+/// HAR conventions: `-1` for unknown sizes, empty strings and lists otherwise
+fn default_for(field: &str) -> Option<Value> {
+    Some(match field {
+        "cookies" | "headers" | "queryString" | "entries" => json!([]),
+        "headersSize" | "bodySize" => json!(-1),
+        "time" | "status" | "hitCount" => json!(0),
+        "startedDateTime" | "httpVersion" | "statusText" | "redirectURL" | "lastAccess"
+        | "eTag" | "mimeType" | "value" => json!(""),
+        "cache" | "timings" => json!({}),
+        "content" => json!({"size": 0, "mimeType": ""}),
+        "creator" => json!({"name": "", "version": ""}),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::har::{Cache, Headers, Timings};
+
+    impl Repairable for Timings {
+        const KIND: &'static str = "timings";
+    }
+
+    impl Repairable for Headers {
+        const KIND: &'static str = "headers";
+    }
+
+    fn repaired<T: Repairable>(value: Value) -> (Result<T, Diagnostic>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let result = deserialize(value, Some(3), &mut |diagnostic| {
+            diagnostics.push(diagnostic)
+        });
+        (result, diagnostics)
+    }
+
+    #[test_case(json!({"dns": 1.6, "send": "2.5"}), Timings { dns: Some(2), send: Some(2.5), ..Default::default() }; "numbers")]
+    #[test_case(json!({"ssl": 2.0, "blocked": "-1"}), Timings { ssl: Some(2), blocked: Some(-1.0), ..Default::default() }; "more numbers")]
+    #[test_case(json!({"dns": "soon", "wait": 3}), Timings { wait: Some(3.0), ..Default::default() }; "hopeless")]
+    fn it_coerces_timings(value: Value, expected: Timings) {
+        let (result, diagnostics) = repaired::<Timings>(value);
+        assert_eq!(result, Ok(expected));
+        assert!(!diagnostics.is_empty());
+    }
+
+    #[test]
+    fn it_coerces_header_values() {
+        let (result, diagnostics) = repaired::<Headers>(json!({"name": "Age", "value": 60}));
+        assert_eq!(result.unwrap().value, "60");
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "entries[3].value: invalid type: integer `60`, expected a string; replaced with \"60\""
+        );
+    }
+
+    #[test]
+    fn it_defaults_missing_fields() {
+        let (result, diagnostics) = repaired::<Entries>(json!({
+            "startedDateTime": "2020-01-01T00:00:00Z",
+            "time": 1,
+            "request": {"method": "GET", "url": "http://example.com/", "httpVersion": "HTTP/1.1"},
+            "response": {"status": "200", "redirectURL": null, "content": {"size": 0, "mimeType": "text/plain"}},
+            "timings": {"send": 0, "wait": 1, "receive": 0}
+        }));
+
+        let entry = result.unwrap();
+        assert_eq!(entry.cache, Cache::default());
+        assert_eq!(entry.request.headers_size, -1);
+        assert_eq!(entry.response.status, 200);
+        assert_eq!(entry.response.redirect_url, "");
+        assert_matches!(
+            diagnostics
+                .iter()
+                .find(|diagnostic| diagnostic.path == "response"),
+            Some(Diagnostic { entry: Some(3), .. })
+        );
+        assert!(diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.path == "." && diagnostic.message.contains("`cache`")));
+    }
+
+    #[test]
+    fn it_repairs_by_shape() {
+        let mut diagnostics = Vec::new();
+        let har = deserialize::<Har>(
+            json!({"log": {"entries": [], "pages": [{"id": 1, "startedDateTime": "", "pageTimings": {}}]}}),
+            None,
+            &mut |diagnostic| diagnostics.push(diagnostic),
+        )
+        .unwrap();
+        assert_eq!(har.log.creator.name, "");
+        assert_eq!(har.log.pages.unwrap()[0].id, "1");
+        assert_matches!(
+            diagnostics.iter().find(|diagnostic| diagnostic.path == "log"),
+            Some(Diagnostic { repair: Some(repair), .. })
+                if repair == "defaulted `creator` to {\"name\":\"\",\"version\":\"\"}"
+        );
+    }
+
+    #[test]
+    fn it_fails_what_it_cannot_repair() {
+        let (result, _) = repaired::<Entries>(json!({
+            "startedDateTime": "2020-01-01T00:00:00Z",
+            "time": 1,
+            "request": {"url": "http://example.com/"},
+            "response": {},
+            "timings": {}
+        }));
+        assert_matches!(
+            result,
+            Err(Diagnostic { entry: Some(3), ref path, ref message, .. })
+                if path == "request" && message.contains("`method`")
+        );
+    }
+}
@@ -1,36 +1,243 @@
+mod compression;
 pub mod errors;
 pub mod generic;
+mod lenient;
+mod stream;
+pub mod typed;
+pub mod validate;
 
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
+pub use compression::{open, Compression};
 pub use errors::HarError;
 use errors::*;
 pub use generic::*;
+pub use lenient::Diagnostic;
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Har {
     pub log: Log,
 }
 
-/// Deserialize a HAR from a path
+/// How `to_writer` lays the JSON out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Compact,
+    Pretty,
+}
+
+/// Expand `paths` into HAR files: files are kept as given, directories are
+/// searched recursively for `*.har` files (compressed ones too) and zip
+/// archives, in path order.
+/// Symbolic links to directories are followed, but each directory is only
+/// searched once.
+pub fn find_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<PathBuf>, HarError> {
+    let mut found = Vec::new();
+    let mut searched = HashSet::new();
+
+    for path in paths.iter().map(AsRef::as_ref) {
+        if path.is_dir() {
+            find_in_directory(path, &mut searched, &mut found)?;
+        } else {
+            found.push(path.to_path_buf());
+        }
+    }
+
+    Ok(found)
+}
+
+fn find_in_directory(
+    directory: &Path,
+    searched: &mut HashSet<PathBuf>,
+    found: &mut Vec<PathBuf>,
+) -> Result<(), HarError> {
+    // Links back up the tree would have us going round in circles
+    let canonical = fs::canonicalize(directory).context(Listing { path: directory })?;
+    if !searched.insert(canonical) {
+        return Ok(());
+    }
+
+    let mut paths = fs::read_dir(directory)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .context(Listing { path: directory })?;
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            find_in_directory(&path, searched, found)?;
+        } else if is_har_file(&path) {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn is_har_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    [".har", ".har.gz", ".har.zst", ".zip"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+/// Deserialize a HAR from a path, decompressing it if needed (see `open`)
 #[cfg_attr(tarpaulin, skip)]
 pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Har, HarError> {
-    from_reader(BufReader::new(File::open(path).context(Opening)?))
+    from_reader(open(path, None)?)
 }
 
-/// Deserialize a HAR from type which implements Read
+/// Deserialize a HAR from type which implements Read; errors tell where in
+/// the HAR the problem is
 pub fn from_reader<R: Read>(read: R) -> Result<Har, HarError> {
-    Ok(serde_json::from_reader::<R, Har>(read).context(Reading)?)
+    let mut deserializer = serde_json::Deserializer::from_reader(read);
+    let har = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|error| serde_json::Error::custom(format!("{}: {}", error.path(), error.inner())));
+    deserializer.end().context(Reading)?;
+    har.context(Reading)
+}
+
+/// Serialize a HAR into a type which implements Write
+pub fn to_writer<W: Write>(har: &Har, write: W, format: Format) -> Result<(), HarError> {
+    match format {
+        Format::Compact => serde_json::to_writer(write, har),
+        Format::Pretty => serde_json::to_writer_pretty(write, har),
+    }
+    .context(Writing)
+}
+
+/// Serialize a HAR into a file at `path`, compressed with `compression` (see
+/// `open` to read it back)
+#[cfg_attr(tarpaulin, skip)]
+pub fn to_path<P: AsRef<Path>>(
+    har: &Har,
+    path: P,
+    format: Format,
+    compression: Compression,
+) -> Result<(), HarError> {
+    compression::create(path, compression, |write| to_writer(har, write, format))
+}
+
+/// Deserialize the entries of a HAR one at a time, handing each one over to
+/// `on_entry` as soon as it's read, so big HARs never have to fit in memory.
+/// Everything else in the HAR is skipped. Returns the number of entries.
+///
+/// The first entry that doesn't fit the HAR model fails the whole thing, with
+/// an error telling which entry, where in it and where in the file the
+/// problem is.
+pub fn stream_from_reader<R: Read, F: FnMut(Entries)>(
+    read: R,
+    on_entry: F,
+) -> Result<usize, HarError> {
+    let mut strict = Strict {
+        on_entry,
+        index: 0,
+        failed_at: None,
+    };
+    stream(read, &mut strict).map_err(|source| match strict.failed_at.take() {
+        Some(location) => HarError::InvalidEntry { source, location },
+        None => HarError::Reading { source },
+    })
+}
+
+/// Like `stream_from_reader`, but repairing what it can of entries that
+/// don't fit the HAR model (missing fields, mistyped numbers, nulls) and
+/// skipping the entries it can't repair. Every repair and skipped entry is
+/// reported to `on_diagnostic`. Returns the number of entries, skipped or not.
+pub fn stream_from_reader_lenient<R, F, D>(
+    read: R,
+    on_entry: F,
+    on_diagnostic: D,
+) -> Result<usize, HarError>
+where
+    R: Read,
+    F: FnMut(Entries),
+    D: FnMut(Diagnostic),
+{
+    stream(
+        read,
+        &mut Lenient {
+            on_entry,
+            on_diagnostic,
+            index: 0,
+        },
+    )
+    .context(Reading)
+}
+
+fn stream<R: Read, E: stream::OnEntry>(read: R, on_entry: &mut E) -> serde_json::Result<usize> {
+    let mut deserializer = serde_json::Deserializer::from_reader(read);
+    let count = deserializer.deserialize_map(stream::HarVisitor(on_entry))?;
+    deserializer.end()?;
+    Ok(count)
+}
+
+/// Deserializes entries straight from the reader, keeping track of where in
+/// the entry deserialization failed, if it does
+struct Strict<F> {
+    on_entry: F,
+    index: usize,
+    failed_at: Option<String>,
+}
+
+impl<F: FnMut(Entries)> stream::OnEntry for Strict<F> {
+    fn on_entry<'de, D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error> {
+        let mut track = serde_path_to_error::Track::new();
+        let deserializer = serde_path_to_error::Deserializer::new(deserializer, &mut track);
+        match Entries::deserialize(deserializer) {
+            Ok(entry) => {
+                (self.on_entry)(entry);
+                self.index += 1;
+                Ok(())
+            }
+            // Passed on as is, for the error to keep its line and column
+            Err(error) => {
+                let path = track.path().to_string();
+                self.failed_at = Some(lenient::location(Some(self.index), &path));
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Reads entries into JSON values first, for `lenient` to repair them
+struct Lenient<F, D> {
+    on_entry: F,
+    on_diagnostic: D,
+    index: usize,
+}
+
+impl<F: FnMut(Entries), D: FnMut(Diagnostic)> stream::OnEntry for Lenient<F, D> {
+    fn on_entry<'de, De: Deserializer<'de>>(&mut self, deserializer: De) -> Result<(), De::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        match lenient::deserialize(value, Some(self.index), &mut self.on_diagnostic) {
+            Ok(entry) => (self.on_entry)(entry),
+            Err(mut diagnostic) => {
+                diagnostic.repair = Some("skipped the entry".into());
+                (self.on_diagnostic)(diagnostic);
+            }
+        }
+        self.index += 1;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert_matches::assert_matches;
+    use test_case::test_case;
 
     #[test]
     fn load_from_not_json_or_har() {
@@ -50,4 +257,348 @@ mod tests {
         let json = br#"{"log":{"version":"1.2","creator":{"name":"Creator?","version":"0.1"},"pages":[],"entries":[]}}"#;
         assert_matches!(from_reader(&json[..]), Ok(_));
     }
+
+    const ROUND_TRIP: &str = r#"{
+        "log": {
+            "version": "1.2",
+            "creator": {"name": "Creator?", "version": "0.1", "comment": "hand made"},
+            "browser": {"name": "Browser", "version": "1"},
+            "pages": [{"startedDateTime": "2020-01-01T00:00:00Z", "id": "page_1", "title": "Home", "pageTimings": {"onContentLoad": 10, "onLoad": 20}}],
+            "entries": [{
+                "pageref": "page_1",
+                "startedDateTime": "2020-01-01T00:00:00.100Z",
+                "time": 12.5,
+                "request": {
+                    "method": "POST", "url": "http://example.com/form?a=1", "httpVersion": "HTTP/1.1",
+                    "cookies": [{"name": "session", "value": "1", "path": "/", "httpOnly": true, "secure": false}],
+                    "headers": [{"name": "Content-Type", "value": "application/x-www-form-urlencoded"}],
+                    "queryString": [{"name": "a", "value": "1"}],
+                    "postData": {"mimeType": "application/x-www-form-urlencoded", "params": [{"name": "b", "value": "2"}]},
+                    "headersSize": 120, "bodySize": 3
+                },
+                "response": {
+                    "status": 200, "statusText": "OK", "httpVersion": "HTTP/1.1",
+                    "cookies": [], "headers": [{"name": "ETag", "value": "\"1\""}],
+                    "content": {"size": 4, "compression": 0, "mimeType": "text/plain", "text": "b3Jp", "encoding": "base64"},
+                    "redirectURL": "", "headersSize": -1, "bodySize": 4, "_transferSize": 130
+                },
+                "cache": {"afterRequest": {"expires": "2020-01-02T00:00:00Z", "lastAccess": "2020-01-01T00:00:00Z", "eTag": "\"1\"", "hitCount": 1}},
+                "timings": {"blocked": -1, "dns": -1, "connect": -1, "send": 0.5, "wait": 10, "receive": 2, "ssl": -1},
+                "serverIPAddress": "127.0.0.1",
+                "connection": "1",
+                "_resourceType": "document",
+                "comment": "first"
+            }]
+        }
+    }"#;
+
+    /// As exported by Chrome's developer tools, vendor fields and all
+    const CHROME: &str = r#"{
+        "log": {
+            "version": "1.2",
+            "creator": {"name": "WebInspector", "version": "537.36"},
+            "pages": [{"startedDateTime": "2024-03-01T10:00:00.000Z", "id": "page_1", "title": "https://example.com/", "pageTimings": {"onContentLoad": 412, "onLoad": 905}}],
+            "entries": [{
+                "_initiator": {"type": "other"},
+                "_priority": "VeryHigh",
+                "_resourceType": "document",
+                "cache": {},
+                "connection": "281",
+                "pageref": "page_1",
+                "request": {
+                    "method": "GET", "url": "https://example.com/?q=1", "httpVersion": "http/2.0",
+                    "headers": [{"name": ":authority", "value": "example.com"}, {"name": "accept", "value": "text/html"}],
+                    "queryString": [{"name": "q", "value": "1"}],
+                    "cookies": [{"name": "session", "value": "1", "path": "/", "domain": "example.com", "expires": "2025-03-01T10:00:00.000Z", "httpOnly": true, "secure": true}],
+                    "headersSize": -1, "bodySize": 0
+                },
+                "response": {
+                    "status": 200, "statusText": "", "httpVersion": "http/2.0",
+                    "headers": [{"name": "content-type", "value": "text/html; charset=UTF-8"}],
+                    "cookies": [],
+                    "content": {"size": 1256, "mimeType": "text/html", "compression": 612, "text": "<!doctype html>"},
+                    "redirectURL": "", "headersSize": -1, "bodySize": -1,
+                    "_transferSize": 644, "_error": null, "_fetchedViaServiceWorker": false
+                },
+                "serverIPAddress": "[2606:4700::6810:85e5]",
+                "startedDateTime": "2024-03-01T10:00:00.123Z",
+                "time": 53.218000000000004,
+                "timings": {
+                    "blocked": 1.735, "dns": -1, "ssl": -1, "connect": -1, "send": 0.152, "wait": 50.031, "receive": 1.3,
+                    "_blocked_queueing": 1.133, "_workerStart": -1, "_workerReady": -1
+                }
+            }, {
+                "_resourceType": "websocket",
+                "_webSocketMessages": [{"type": "send", "time": 1709287200.5, "opcode": 1, "data": "hi"}, {"type": "receive", "time": 1709287201, "opcode": 1, "data": "hello"}],
+                "cache": {},
+                "request": {"method": "GET", "url": "wss://example.com/live", "httpVersion": "HTTP/1.1", "headers": [], "queryString": [], "cookies": [], "headersSize": -1, "bodySize": 0},
+                "response": {"status": 101, "statusText": "Switching Protocols", "httpVersion": "HTTP/1.1", "headers": [], "cookies": [], "content": {"size": 0, "mimeType": "x-unknown"}, "redirectURL": "", "headersSize": -1, "bodySize": 0, "_transferSize": 0, "_error": null},
+                "startedDateTime": "2024-03-01T10:00:01.000Z",
+                "time": 1.5,
+                "timings": {"blocked": -1, "dns": -1, "ssl": -1, "connect": -1, "send": 0, "wait": 1.5, "receive": 0}
+            }]
+        }
+    }"#;
+
+    /// As exported by Firefox's developer tools
+    const FIREFOX: &str = r#"{
+        "log": {
+            "version": "1.2",
+            "creator": {"name": "Firefox", "version": "123.0"},
+            "browser": {"name": "Firefox", "version": "123.0"},
+            "pages": [{"startedDateTime": "2024-03-01T11:00:00.000+01:00", "id": "page_1", "pageTimings": {"onContentLoad": 120, "onLoad": 245}, "title": "Example Domain"}],
+            "entries": [{
+                "pageref": "page_1",
+                "startedDateTime": "2024-03-01T11:00:00.010+01:00",
+                "request": {
+                    "bodySize": 0, "method": "GET", "url": "https://example.com/", "httpVersion": "HTTP/2",
+                    "headers": [{"name": "Host", "value": "example.com"}, {"name": "Accept-Encoding", "value": "gzip, deflate, br"}],
+                    "cookies": [], "queryString": [], "headersSize": 412
+                },
+                "response": {
+                    "status": 200, "statusText": "OK", "httpVersion": "HTTP/2",
+                    "headers": [{"name": "content-type", "value": "text/html; charset=UTF-8"}, {"name": "content-encoding", "value": "gzip"}],
+                    "cookies": [],
+                    "content": {"mimeType": "text/html; charset=UTF-8", "size": 1256, "text": "<!doctype html>"},
+                    "redirectURL": "", "headersSize": 312, "bodySize": 923
+                },
+                "cache": {},
+                "timings": {"blocked": 0, "dns": 12, "connect": 20, "ssl": 15, "send": 0, "wait": 40, "receive": 2},
+                "time": 89,
+                "_securityState": "secure",
+                "serverIPAddress": "93.184.216.34",
+                "connection": "443"
+            }]
+        }
+    }"#;
+
+    #[test_case(CHROME; "chrome")]
+    #[test_case(FIREFOX; "firefox")]
+    fn write_browser_hars_back_as_they_were(json: &str) {
+        let original: serde_json::Value = serde_json::from_str(json).unwrap();
+        let har = from_reader(json.as_bytes()).unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("written.har");
+
+        to_path(&har, &path, Format::Pretty, Compression::None).unwrap();
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written, original);
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let original: serde_json::Value = serde_json::from_str(ROUND_TRIP).unwrap();
+        let har = from_reader(ROUND_TRIP.as_bytes()).unwrap();
+
+        for format in &[Format::Compact, Format::Pretty] {
+            let mut written = Vec::new();
+            to_writer(&har, &mut written, *format).unwrap();
+            let rewritten: serde_json::Value = serde_json::from_slice(&written).unwrap();
+            assert_eq!(rewritten, original);
+        }
+
+        let mut compact = Vec::new();
+        to_writer(&har, &mut compact, Format::Compact).unwrap();
+        assert!(!compact.contains(&b'\n'));
+    }
+
+    #[test]
+    fn write_to_paths() {
+        let har = from_reader(ROUND_TRIP.as_bytes()).unwrap();
+        let directory = tempfile::tempdir().unwrap();
+
+        for compression in &[Compression::None, Compression::Gzip, Compression::Zstd] {
+            let path = directory.path().join("written.har");
+            to_path(&har, &path, Format::Pretty, *compression).unwrap();
+            assert_eq!(from_path(&path).unwrap(), har);
+        }
+
+        assert_matches!(
+            to_path(
+                &har,
+                directory.path().join("written.zip"),
+                Format::Compact,
+                Compression::Zip
+            ),
+            Err(HarError::Archive { .. })
+        );
+    }
+
+    #[test]
+    fn find_har_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        for path in &[
+            "b.har",
+            "a.har.gz",
+            "notes.txt",
+            "nested/deeper/c.HAR",
+            "nested/d.har.zst",
+            "nested/e.json",
+            "nested/f.zip",
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let found = find_files(&[root.join("notes.txt"), root.to_path_buf()]).unwrap();
+        let found: Vec<_> = found
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                "notes.txt",
+                "a.har.gz",
+                "b.har",
+                "nested/d.har.zst",
+                "nested/deeper/c.HAR",
+                "nested/f.zip",
+            ]
+        );
+
+        assert_matches!(
+            find_files(&[root.join("missing")]),
+            Ok(paths) if paths == vec![root.join("missing")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_har_files_through_links() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("nested/a.har"), "").unwrap();
+        std::os::unix::fs::symlink(root, root.join("nested/loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("nested"), root.join("link")).unwrap();
+
+        let found = find_files(&[root]).unwrap();
+        let found: Vec<_> = found
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(found, vec!["link/a.har"]);
+    }
+
+    #[test]
+    fn stream_entries() {
+        let entry = r#"{"startedDateTime":"2020-01-01T00:00:00Z","time":1,"request":{"method":"GET","url":"http://example.com/PATH","httpVersion":"HTTP/1.1","cookies":[],"headers":[],"queryString":[],"headersSize":-1,"bodySize":0},"response":{"status":200,"statusText":"OK","httpVersion":"HTTP/1.1","cookies":[],"headers":[],"content":{"size":0,"mimeType":"text/plain"},"redirectURL":"","headersSize":-1,"bodySize":0},"cache":{},"timings":{"send":0,"wait":1,"receive":0}}"#;
+        let json = format!(
+            r#"{{"log":{{"version":"1.2","entries":[{},{}],"creator":{{"name":"Creator?","version":"0.1"}}}},"extra":true}}"#,
+            entry.replace("PATH", "a"),
+            entry.replace("PATH", "b"),
+        );
+
+        let mut urls = Vec::new();
+        assert_matches!(
+            stream_from_reader(json.as_bytes(), |entry| urls.push(entry.request.url)),
+            Ok(2)
+        );
+        assert_eq!(urls, vec!["http://example.com/a", "http://example.com/b"]);
+    }
+
+    #[test]
+    fn keep_vendor_fields() {
+        let json = r#"{"startedDateTime":"2020-01-01T00:00:00Z","time":1,"_initiator":{"type":"parser"},"_priority":"High","_resourceType":"script","_fromCache":"disk","unknown":1,"request":{"method":"GET","url":"http://example.com/","httpVersion":"HTTP/1.1","cookies":[],"headers":[{"name":"Accept","value":"*/*","_flag":true}],"queryString":[],"headersSize":-1,"bodySize":0},"response":{"status":200,"statusText":"OK","httpVersion":"HTTP/1.1","cookies":[],"headers":[],"content":{"size":0,"mimeType":"text/plain"},"redirectURL":"","headersSize":-1,"bodySize":0,"_transferSize":120,"_charlesStatus":"COMPLETE"},"cache":{},"timings":{"send":0,"wait":1,"receive":0,"_blocked_queueing":1.5}}"#;
+
+        let entry: Entries = serde_json::from_str(json).unwrap();
+        assert_eq!(entry.resource_type(), Some("script"));
+        assert_eq!(entry.browser_cache(), Some("disk"));
+        assert!(entry.is_from_cache());
+        assert_eq!(entry.extensions.get_str("_priority"), Some("High"));
+        assert_eq!(
+            entry.timings.extensions.get("_blocked_queueing"),
+            Some(&1.5.into())
+        );
+        assert_eq!(entry.response.charles_status.as_deref(), Some("COMPLETE"));
+
+        let serialized = serde_json::to_value(&entry).unwrap();
+        assert_eq!(serialized["_initiator"]["type"], "parser");
+        assert_eq!(serialized["request"]["headers"][0]["_flag"], true);
+        assert_eq!(serialized["response"]["_transferSize"], 120);
+        assert_eq!(serialized["timings"]["_blocked_queueing"], 1.5);
+        assert_eq!(serialized.get("unknown"), None);
+        assert_eq!(
+            serde_json::from_value::<Entries>(serialized).unwrap(),
+            entry
+        );
+    }
+
+    #[test]
+    fn stream_leniently() {
+        let entry = r#"{"startedDateTime":"2020-01-01T00:00:00Z","time":1,"request":{"method":"GET","url":"http://example.com/PATH","httpVersion":"HTTP/1.1","headersSize":-1,"bodySize":0},"response":{"status":200,"statusText":"OK","httpVersion":"HTTP/1.1","cookies":[],"headers":[],"content":{"size":0,"mimeType":"text/plain"},"headersSize":-1,"bodySize":0},"timings":{"dns":1.5,"send":0,"wait":1,"receive":0}}"#;
+        let json = format!(
+            r#"{{"log":{{"entries":[{},{{"request":{{}}}},{}]}}}}"#,
+            entry.replace("PATH", "a"),
+            entry.replace("PATH", "b"),
+        );
+
+        assert_matches!(
+            stream_from_reader(json.as_bytes(), |_| {}),
+            Err(error @ HarError::InvalidEntry { .. })
+                if error.to_string().starts_with("Invalid entries[0].request: missing field `cookies`")
+        );
+
+        let mut urls = Vec::new();
+        let mut diagnostics = Vec::new();
+        assert_matches!(
+            stream_from_reader_lenient(
+                json.as_bytes(),
+                |entry| urls.push(entry.request.url),
+                |diagnostic| diagnostics.push(diagnostic)
+            ),
+            Ok(3)
+        );
+        assert_eq!(urls, vec!["http://example.com/a", "http://example.com/b"]);
+
+        let skipped: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.entry == Some(1))
+            .map(ToString::to_string)
+            .collect();
+        assert_matches!(
+            skipped.last(),
+            Some(message) if message.ends_with("skipped the entry")
+        );
+        assert!(diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.entry == Some(2) && diagnostic.path == "timings.dns"));
+    }
+
+    #[test]
+    fn stream_errors_locate_the_entry() {
+        let json = "{\"log\": {\"entries\": [\n{\"request\": {\"method\": 1}}]}}";
+        let error = stream_from_reader(json.as_bytes(), |_| {}).unwrap_err();
+        assert_matches!(
+            &error,
+            HarError::InvalidEntry { location, source }
+                if location == "entries[0].request.method" && source.line() == 2
+        );
+    }
+
+    #[test]
+    fn stream_from_not_json_or_har() {
+        let ignore = |_: Entries| {};
+        assert_matches!(stream_from_reader(&b"[]"[..], ignore), Err(_));
+        assert_matches!(
+            stream_from_reader(&br#"{"some": "json"}"#[..], ignore),
+            Err(_)
+        );
+        assert_matches!(stream_from_reader(&br#"{"log": {}}"#[..], ignore), Err(_));
+        assert_matches!(
+            stream_from_reader(&br#"{"log": {"entries": [{}]}}"#[..], ignore),
+            Err(_)
+        );
+        assert_matches!(
+            stream_from_reader(&br#"{"log": {"entries": []}} trailing"#[..], ignore),
+            Err(_)
+        );
+        assert_matches!(
+            stream_from_reader(&br#"{"log": {"entries": []}}"#[..], ignore),
+            Ok(0)
+        );
+    }
 }
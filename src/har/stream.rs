@@ -0,0 +1,110 @@
+//! Visitors walking `{"log": {"entries": [...]}}` without keeping more than
+//! one entry in memory at a time. Everything but the entries is skipped.
+//!
+//! Each entry is handed over to an `OnEntry` straight from the deserializer,
+//! for it to deserialize however it sees fit; returning an error stops the
+//! whole thing.
+
+use std::fmt;
+
+use serde::de::{
+    DeserializeSeed, Deserializer, Error as _, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+
+/// What to do with each entry
+pub trait OnEntry {
+    fn on_entry<'de, D: Deserializer<'de>>(&mut self, deserializer: D) -> Result<(), D::Error>;
+}
+
+/// The top level object, handing the `log` over to `LogVisitor`
+pub struct HarVisitor<'a, F>(pub &'a mut F);
+
+/// The `log` object, handing the `entries` over to `EntriesVisitor`
+struct LogVisitor<'a, F>(&'a mut F);
+
+/// The `entries` array, handing each entry over to `EntrySeed`
+struct EntriesVisitor<'a, F>(&'a mut F);
+
+/// A single entry, handed over to the `OnEntry`
+struct EntrySeed<'a, F>(&'a mut F);
+
+impl<'de, 'a, F: OnEntry> Visitor<'de> for HarVisitor<'a, F> {
+    /// Number of entries
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a HAR object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut count = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "log" {
+                count = Some(map.next_value_seed(LogVisitor(&mut *self.0))?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        count.ok_or_else(|| A::Error::missing_field("log"))
+    }
+}
+
+impl<'de, 'a, F: OnEntry> DeserializeSeed<'de> for LogVisitor<'a, F> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, F: OnEntry> Visitor<'de> for LogVisitor<'a, F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a HAR log object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut count = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entries" {
+                count = Some(map.next_value_seed(EntriesVisitor(&mut *self.0))?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        count.ok_or_else(|| A::Error::missing_field("entries"))
+    }
+}
+
+impl<'de, 'a, F: OnEntry> DeserializeSeed<'de> for EntriesVisitor<'a, F> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, F: OnEntry> Visitor<'de> for EntriesVisitor<'a, F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of HAR entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut count = 0;
+        while let Some(()) = seq.next_element_seed(EntrySeed(&mut *self.0))? {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<'de, 'a, F: OnEntry> DeserializeSeed<'de> for EntrySeed<'a, F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.0.on_entry(deserializer)
+    }
+}
@@ -0,0 +1,339 @@
+//! A typed layer over the raw `generic` model: methods, status codes,
+//! headers, HTTP versions and dates parsed into their `http` and `chrono`
+//! types, and post data as either text or params. Converting from the raw
+//! model checks all of them, so users don't have to.
+
+use std::convert::TryFrom;
+
+use chrono::{DateTime, FixedOffset};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::{Method, StatusCode, Version};
+use url::Url;
+
+use super::errors::*;
+use super::generic::{self, Cache, Content, Cookies, Extensions, Params, QueryString, Timings};
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub pageref: Option<String>,
+    pub started_date_time: DateTime<FixedOffset>,
+    pub time: f64,
+    pub request: Request,
+    pub response: Response,
+    pub cache: Cache,
+    pub timings: Timings,
+    pub server_ip_address: Option<String>,
+    pub connection: Option<String>,
+    pub comment: Option<String>,
+    pub extensions: Extensions,
+}
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    pub url: Url,
+    /// `None` when the exporter didn't know
+    pub http_version: Option<Version>,
+    pub cookies: Vec<Cookies>,
+    /// HTTP/2 pseudo-headers (`:authority` and such) are left out
+    pub headers: HeaderMap,
+    pub query_string: Vec<QueryString>,
+    pub post_data: Option<PostData>,
+    /// `None` when unknown (`-1` in the HAR)
+    pub headers_size: Option<u64>,
+    /// `None` when unknown (`-1` in the HAR)
+    pub body_size: Option<u64>,
+    pub comment: Option<String>,
+    pub extensions: Extensions,
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub status_text: String,
+    /// `None` when the exporter didn't know
+    pub http_version: Option<Version>,
+    pub cookies: Vec<Cookies>,
+    /// HTTP/2 pseudo-headers (`:status`) are left out
+    pub headers: HeaderMap,
+    pub content: Content,
+    pub redirect_url: String,
+    /// `None` when unknown (`-1` in the HAR)
+    pub headers_size: Option<u64>,
+    /// `None` when unknown (`-1` in the HAR)
+    pub body_size: Option<u64>,
+    pub comment: Option<String>,
+    pub extensions: Extensions,
+}
+
+/// A request body, as recorded text or as form params
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostData {
+    Text {
+        mime_type: String,
+        text: String,
+    },
+    Params {
+        mime_type: String,
+        params: Vec<Params>,
+    },
+}
+
+impl TryFrom<&generic::Entries> for Entry {
+    type Error = HarError;
+
+    fn try_from(entry: &generic::Entries) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pageref: entry.pageref.clone(),
+            started_date_time: parse_date(&entry.started_date_time)?,
+            time: entry.time,
+            request: Request::try_from(&entry.request)?,
+            response: Response::try_from(&entry.response)?,
+            cache: entry.cache.clone(),
+            timings: entry.timings.clone(),
+            server_ip_address: entry.server_ip_address.clone(),
+            connection: entry.connection.clone(),
+            comment: entry.comment.clone(),
+            extensions: entry.extensions.clone(),
+        })
+    }
+}
+
+impl TryFrom<&generic::Request> for Request {
+    type Error = HarError;
+
+    fn try_from(request: &generic::Request) -> Result<Self, Self::Error> {
+        Ok(Self {
+            method: parse_method(&request.method)?,
+            url: parse_url(&request.url)?,
+            http_version: parse_version(&request.http_version)?,
+            cookies: request.cookies.clone(),
+            headers: parse_headers(&request.headers)?,
+            query_string: request.query_string.clone(),
+            post_data: request.post_data.as_ref().map(PostData::from),
+            headers_size: size(request.headers_size),
+            body_size: size(request.body_size),
+            comment: request.comment.clone(),
+            extensions: request.extensions.clone(),
+        })
+    }
+}
+
+impl TryFrom<&generic::Response> for Response {
+    type Error = HarError;
+
+    fn try_from(response: &generic::Response) -> Result<Self, Self::Error> {
+        Ok(Self {
+            status: parse_status(response.status)?,
+            status_text: response.status_text.clone(),
+            http_version: parse_version(&response.http_version)?,
+            cookies: response.cookies.clone(),
+            headers: parse_headers(&response.headers)?,
+            content: response.content.clone(),
+            redirect_url: response.redirect_url.clone(),
+            headers_size: size(response.headers_size),
+            body_size: size(response.body_size),
+            comment: response.comment.clone(),
+            extensions: response.extensions.clone(),
+        })
+    }
+}
+
+/// Text and params are supposed to be exclusive, but some exporters record
+/// both for forms; the text wins then, being the actual body.
+impl From<&generic::PostData> for PostData {
+    fn from(post_data: &generic::PostData) -> Self {
+        let mime_type = post_data.mime_type.clone();
+        match (&post_data.text, &post_data.params) {
+            (None, Some(params)) => Self::Params {
+                mime_type,
+                params: params.clone(),
+            },
+            (text, _) => Self::Text {
+                mime_type,
+                text: text.clone().unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Parse a HAR date, ISO 8601 as in `2009-07-24T19:20:30.45+01:00`
+pub fn parse_date(date: &str) -> Result<DateTime<FixedOffset>, HarError> {
+    DateTime::parse_from_rfc3339(date).context(InvalidDate { date })
+}
+
+pub fn parse_method(method: &str) -> Result<Method, HarError> {
+    Method::from_bytes(method.as_bytes()).context(InvalidMethod { method })
+}
+
+pub fn parse_url(url: &str) -> Result<Url, HarError> {
+    Url::parse(url).context(InvalidUrl { url })
+}
+
+pub fn parse_status(status: i64) -> Result<StatusCode, HarError> {
+    u16::try_from(status)
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .context(InvalidStatus { status })
+}
+
+/// `None` when the exporter didn't know, as in `""` or `"unknown"`
+pub fn parse_version(version: &str) -> Result<Option<Version>, HarError> {
+    Ok(Some(match version.to_ascii_uppercase().as_str() {
+        "" | "UNKNOWN" => return Ok(None),
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/1.1" => Version::HTTP_11,
+        "HTTP/2" | "HTTP/2.0" | "H2" | "H2C" => Version::HTTP_2,
+        // Chrome records drafts as `h3-29` and such
+        "HTTP/3" | "HTTP/3.0" => Version::HTTP_3,
+        upper if upper.starts_with("H3") => Version::HTTP_3,
+        _ => return InvalidHttpVersion { version }.fail(),
+    }))
+}
+
+/// `None` for HTTP/2 pseudo-headers (`:authority` and such), which aren't
+/// real headers
+pub fn parse_header(
+    header: &generic::Headers,
+) -> Result<Option<(HeaderName, HeaderValue)>, HarError> {
+    if header.name.starts_with(':') {
+        return Ok(None);
+    }
+    let name = HeaderName::from_bytes(header.name.as_bytes())
+        .context(InvalidHeaderName { name: &header.name })?;
+    let value =
+        HeaderValue::from_str(&header.value).context(InvalidHeaderValue { name: &header.name })?;
+    Ok(Some((name, value)))
+}
+
+fn parse_headers(headers: &[generic::Headers]) -> Result<HeaderMap, HarError> {
+    let mut parsed = HeaderMap::with_capacity(headers.len());
+    for header in headers {
+        if let Some((name, value)) = parse_header(header)? {
+            parsed.append(name, value);
+        }
+    }
+    Ok(parsed)
+}
+
+/// HAR sizes are `-1` when unknown
+fn size(size: i64) -> Option<u64> {
+    u64::try_from(size).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn entry() -> generic::Entries {
+        serde_json::from_str(
+            r#"{
+                "startedDateTime": "2020-01-01T10:00:00.123+01:00",
+                "time": 12.5,
+                "request": {
+                    "method": "POST", "url": "https://example.com/form?a=1", "httpVersion": "h2",
+                    "cookies": [], "queryString": [{"name": "a", "value": "1"}],
+                    "headers": [
+                        {"name": ":authority", "value": "example.com"},
+                        {"name": "Accept", "value": "text/html"},
+                        {"name": "accept", "value": "*/*"}
+                    ],
+                    "postData": {"mimeType": "application/x-www-form-urlencoded", "params": [{"name": "b", "value": "2"}]},
+                    "headersSize": -1, "bodySize": 3
+                },
+                "response": {
+                    "status": 201, "statusText": "Created", "httpVersion": "",
+                    "cookies": [], "headers": [{"name": "Content-Type", "value": "text/plain"}],
+                    "content": {"size": 2, "mimeType": "text/plain", "text": "ok"},
+                    "redirectURL": "", "headersSize": 42, "bodySize": -1
+                },
+                "cache": {},
+                "timings": {"send": 0, "wait": 10, "receive": 2.5}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_types_entries() {
+        let entry = Entry::try_from(&entry()).unwrap();
+
+        assert_eq!(
+            entry.started_date_time.timestamp_millis(),
+            1_577_869_200_123
+        );
+        assert_eq!(entry.request.method, Method::POST);
+        assert_eq!(entry.request.url.query(), Some("a=1"));
+        assert_eq!(entry.request.http_version, Some(Version::HTTP_2));
+        assert_eq!(entry.request.headers.len(), 2);
+        assert_eq!(entry.request.headers.get_all("accept").iter().count(), 2);
+        assert_eq!(entry.request.headers_size, None);
+        assert_eq!(entry.request.body_size, Some(3));
+        assert_matches!(entry.request.post_data, Some(PostData::Params { ref params, .. }) if params.len() == 1);
+        assert_eq!(entry.response.status, StatusCode::CREATED);
+        assert_eq!(entry.response.http_version, None);
+        assert_eq!(entry.response.headers["content-type"], "text/plain");
+        assert_eq!(entry.response.headers_size, Some(42));
+        assert_eq!(entry.response.body_size, None);
+    }
+
+    #[test]
+    fn it_rejects_what_does_not_parse() {
+        let mut broken = entry();
+        broken.started_date_time = "yesterday".into();
+        assert_matches!(Entry::try_from(&broken), Err(HarError::InvalidDate { .. }));
+
+        let mut broken = entry();
+        broken.request.method = "GET IT".into();
+        assert_matches!(
+            Entry::try_from(&broken),
+            Err(HarError::InvalidMethod { .. })
+        );
+
+        let mut broken = entry();
+        broken.request.http_version = "SPDY/3".into();
+        assert_matches!(
+            Entry::try_from(&broken),
+            Err(HarError::InvalidHttpVersion { .. })
+        );
+
+        let mut broken = entry();
+        broken.response.status = 0;
+        assert_matches!(
+            Entry::try_from(&broken),
+            Err(HarError::InvalidStatus { status: 0 })
+        );
+
+        let mut broken = entry();
+        broken.response.headers[0].name = "Content Type".into();
+        assert_matches!(
+            Entry::try_from(&broken),
+            Err(HarError::InvalidHeaderName { .. })
+        );
+
+        let mut broken = entry();
+        broken.request.headers[1].value = "text/html\n".into();
+        assert_matches!(
+            Entry::try_from(&broken),
+            Err(HarError::InvalidHeaderValue { .. })
+        );
+    }
+
+    #[test_case(Some("a=1"), None, PostData::Text { mime_type: "text/plain".into(), text: "a=1".into() }; "text")]
+    #[test_case(Some("a=1"), Some(vec![]), PostData::Text { mime_type: "text/plain".into(), text: "a=1".into() }; "both")]
+    #[test_case(None, Some(vec![]), PostData::Params { mime_type: "text/plain".into(), params: vec![] }; "params")]
+    #[test_case(None, None, PostData::Text { mime_type: "text/plain".into(), text: "".into() }; "neither")]
+    fn it_types_post_data(text: Option<&str>, params: Option<Vec<Params>>, expected: PostData) {
+        let post_data = generic::PostData {
+            mime_type: "text/plain".into(),
+            text: text.map(Into::into),
+            params,
+            ..Default::default()
+        };
+        assert_eq!(PostData::from(&post_data), expected);
+    }
+}
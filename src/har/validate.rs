@@ -0,0 +1,591 @@
+//! Checking HARs against the HAR 1.2 spec, beyond fitting the `generic` model:
+//! dates, the `-1` convention for unknown sizes and timings, page references
+//! and body sizes. Problems are told by their line and column in the file.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde_json::Value;
+
+use super::lenient::{self, Diagnostic};
+use super::{typed, Entries, Har, HarError};
+
+/// Something in a HAR not following the spec
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// JSON path to the problem, as `log.entries[3].response.bodySize`
+    pub path: String,
+    /// Line and column where the value at `path` starts, both from 1
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if let Some((line, column)) = self.position {
+            write!(formatter, "{}:{}: ", line, column)?;
+        }
+        write!(formatter, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Check the HAR in `json`, returning every problem found in file order
+pub fn validate(json: &str) -> Vec<Problem> {
+    let mut value: Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(error) => {
+            return vec![Problem {
+                path: ".".into(),
+                position: Some((error.line(), error.column())),
+                message: error.to_string(),
+            }]
+        }
+    };
+
+    let mut problems = Vec::new();
+    check_required(&value, "har", "", &mut problems);
+
+    let mut report = |diagnostic: Diagnostic| {
+        let path = match diagnostic.entry {
+            Some(_) => format!(
+                "log.{}",
+                lenient::location(diagnostic.entry, &diagnostic.path)
+            ),
+            None => diagnostic.path,
+        };
+        problems.push((path, diagnostic.message));
+    };
+
+    // Entries go one by one, so a broken one doesn't hide problems in the others
+    let entries = match value.pointer_mut("/log/entries") {
+        Some(Value::Array(entries)) => std::mem::take(entries),
+        _ => Vec::new(),
+    };
+
+    let har = lenient::deserialize::<Har>(value, None, &mut report);
+    let entries: Vec<_> = entries
+        .into_iter()
+        .enumerate()
+        .filter_map(
+            |(index, entry)| match lenient::deserialize(entry, Some(index), &mut report) {
+                Ok(entry) => Some((index, entry)),
+                Err(diagnostic) => {
+                    report(diagnostic);
+                    None
+                }
+            },
+        )
+        .collect();
+
+    match har {
+        Ok(har) => check(&har, &entries, &mut problems),
+        Err(diagnostic) => report(diagnostic),
+    }
+
+    // Fields both the spec and the model require are missed twice
+    let mut seen = BTreeSet::new();
+    problems.retain(|problem| seen.insert(problem.clone()));
+
+    locate(json, problems)
+}
+
+/// Fields the HAR 1.2 spec requires, by the name of the objects holding them.
+/// The model does without some of them (`log.version`, `pages[].title`...),
+/// so loading HARs leniently doesn't miss them.
+fn required(kind: &str) -> &'static [&'static str] {
+    match kind {
+        "har" => &["log"],
+        "log" => &["version", "creator", "entries"],
+        "creator" | "browser" => &["name", "version"],
+        "pages" => &["startedDateTime", "id", "title", "pageTimings"],
+        "entries" => &[
+            "startedDateTime",
+            "time",
+            "request",
+            "response",
+            "cache",
+            "timings",
+        ],
+        "request" => &[
+            "method",
+            "url",
+            "httpVersion",
+            "cookies",
+            "headers",
+            "queryString",
+            "headersSize",
+            "bodySize",
+        ],
+        "response" => &[
+            "status",
+            "statusText",
+            "httpVersion",
+            "cookies",
+            "headers",
+            "content",
+            "redirectURL",
+            "headersSize",
+            "bodySize",
+        ],
+        "cookies" | "headers" | "queryString" => &["name", "value"],
+        "postData" => &["mimeType"],
+        "params" => &["name"],
+        "content" => &["size", "mimeType"],
+        "beforeRequest" | "afterRequest" => &["lastAccess", "eTag", "hitCount"],
+        "timings" => &["send", "wait", "receive"],
+        _ => &[],
+    }
+}
+
+/// Report the fields `required` says are missing from `value`, an object of
+/// `kind` at `path` (or an array of them), and from what's in it
+fn check_required(value: &Value, kind: &str, path: &str, problems: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for field in required(kind) {
+                if !fields.contains_key(*field) {
+                    let path = if path.is_empty() { "." } else { path };
+                    problems.push((path.into(), format!("missing field `{}`", field)));
+                }
+            }
+            // Vendor fields are up to their vendors
+            for (name, value) in fields.iter().filter(|(name, _)| !name.starts_with('_')) {
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                check_required(value, name, &path, problems);
+            }
+        }
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                check_required(value, kind, &format!("{}[{}]", path, index), problems);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check(har: &Har, entries: &[(usize, Entries)], problems: &mut Vec<(String, String)>) {
+    let pages = har.log.pages.as_deref().unwrap_or_default();
+    for (index, page) in pages.iter().enumerate() {
+        check_date(
+            format!("log.pages[{}].startedDateTime", index),
+            &page.started_date_time,
+            problems,
+        );
+    }
+
+    let page_ids: BTreeSet<_> = pages.iter().map(|page| page.id.as_str()).collect();
+    for (index, entry) in entries {
+        check_entry(*index, entry, &page_ids, problems);
+    }
+}
+
+fn check_entry(
+    index: usize,
+    entry: &Entries,
+    page_ids: &BTreeSet<&str>,
+    problems: &mut Vec<(String, String)>,
+) {
+    let path = |field: &str| format!("log.entries[{}].{}", index, field);
+
+    check_date(path("startedDateTime"), &entry.started_date_time, problems);
+
+    if let Some(pageref) = &entry.pageref {
+        if !page_ids.contains(pageref.as_str()) {
+            problems.push((path("pageref"), format!("no page with id {:?}", pageref)));
+        }
+    }
+
+    if entry.time < 0.0 {
+        problems.push((path("time"), "negative time".into()));
+    }
+
+    for (field, size) in &[
+        ("request.headersSize", entry.request.headers_size),
+        ("request.bodySize", entry.request.body_size),
+        ("response.headersSize", entry.response.headers_size),
+        ("response.bodySize", entry.response.body_size),
+    ] {
+        if *size < -1 {
+            problems.push((path(field), unknown_as_minus_one(*size as f64)));
+        }
+    }
+
+    let timings = &entry.timings;
+    for (field, timing) in &[
+        ("timings.blocked", timings.blocked),
+        ("timings.dns", timings.dns.map(|dns| dns as f64)),
+        ("timings.connect", timings.connect),
+        ("timings.ssl", timings.ssl.map(|ssl| ssl as f64)),
+    ] {
+        match timing {
+            Some(timing) if *timing < 0.0 && *timing != -1.0 => {
+                problems.push((path(field), unknown_as_minus_one(*timing)))
+            }
+            _ => {}
+        }
+    }
+    for (field, timing) in &[
+        ("send", timings.send),
+        ("wait", timings.wait),
+        ("receive", timings.receive),
+    ] {
+        match timing {
+            Some(timing) if *timing < 0.0 => problems.push((
+                path(&format!("timings.{}", field)),
+                format!("negative timing {}", timing),
+            )),
+            _ => {}
+        }
+    }
+
+    // Methods, URLs, versions, status codes and headers, as the typed model
+    // parses them
+    let (request, response) = (&entry.request, &entry.response);
+    let mut report = |field: &str, result: Result<(), HarError>| {
+        if let Err(error) = result {
+            problems.push((path(field), error.to_string()));
+        }
+    };
+    report(
+        "request.method",
+        typed::parse_method(&request.method).map(drop),
+    );
+    report("request.url", typed::parse_url(&request.url).map(drop));
+    report(
+        "response.status",
+        typed::parse_status(response.status).map(drop),
+    );
+    for (side, version, headers) in &[
+        ("request", &request.http_version, &request.headers),
+        ("response", &response.http_version, &response.headers),
+    ] {
+        report(
+            &format!("{}.httpVersion", side),
+            typed::parse_version(version).map(drop),
+        );
+        for header in headers.iter() {
+            report(
+                &format!("{}.headers", side),
+                typed::parse_header(header).map(drop),
+            );
+        }
+    }
+
+    check_body_size(entry, problems, path);
+}
+
+/// The response `bodySize` is what went over the wire, `content.size` the
+/// decoded body: they only differ by `content.compression`, or when the body
+/// was encoded
+fn check_body_size(
+    entry: &Entries,
+    problems: &mut Vec<(String, String)>,
+    path: impl Fn(&str) -> String,
+) {
+    let response = &entry.response;
+    // Missing sizes are told along with other missing fields
+    let size = match response.content.size {
+        Some(size) => size as i64,
+        None => return,
+    };
+    let body_size = response.body_size;
+    if body_size <= 0 {
+        return;
+    }
+
+    let mismatch = match response.content.compression {
+        Some(compression) => size - body_size != compression,
+        None => {
+            let encoded = response
+                .headers
+                .iter()
+                .any(|header| header.name.eq_ignore_ascii_case("content-encoding"));
+            !encoded && size != body_size
+        }
+    };
+    if mismatch {
+        problems.push((
+            path("response.bodySize"),
+            format!(
+                "{} doesn't match content.size {} and compression {}",
+                body_size,
+                size,
+                response.content.compression.unwrap_or_default()
+            ),
+        ));
+    }
+}
+
+fn check_date(path: String, date: &str, problems: &mut Vec<(String, String)>) {
+    if typed::parse_date(date).is_err() {
+        problems.push((path, format!("{:?} is not an ISO 8601 date and time", date)));
+    }
+}
+
+fn unknown_as_minus_one(value: f64) -> String {
+    format!("invalid value {}, use -1 when unknown", value)
+}
+
+/// Find where each problem is in `json`, sorting them in file order
+fn locate(json: &str, problems: Vec<(String, String)>) -> Vec<Problem> {
+    let paths: BTreeSet<_> = problems.iter().map(|(path, _)| path.clone()).collect();
+    let mut scanner = Scanner {
+        json: json.as_bytes(),
+        index: 0,
+        line: 1,
+        column: 0,
+        paths: &paths,
+        positions: HashMap::new(),
+    };
+    scanner.value(&mut String::new());
+    let positions = scanner.positions;
+
+    let mut problems: Vec<_> = problems
+        .into_iter()
+        .map(|(path, message)| Problem {
+            position: positions.get(&path).copied(),
+            path,
+            message,
+        })
+        .collect();
+    problems.sort_by_key(|problem| problem.position);
+    problems
+}
+
+/// Just enough of a JSON parser to tell where the values at some paths start.
+/// The JSON is known to be valid by then.
+struct Scanner<'a> {
+    json: &'a [u8],
+    index: usize,
+    line: usize,
+    column: usize,
+    paths: &'a BTreeSet<String>,
+    positions: HashMap<String, (usize, usize)>,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.json.get(self.index).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.index += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(byte)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') = self.peek() {
+            self.bump();
+        }
+    }
+
+    fn value(&mut self, path: &mut String) -> Option<()> {
+        self.skip_whitespace();
+        let key = if path.is_empty() { "." } else { path.as_str() };
+        if self.paths.contains(key) {
+            self.positions
+                .insert(key.to_owned(), (self.line, self.column + 1));
+        }
+
+        match self.peek()? {
+            b'{' => {
+                self.bump();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek()? == b'}' {
+                        self.bump();
+                        return Some(());
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.bump();
+
+                    let length = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(&key);
+                    self.value(path)?;
+                    path.truncate(length);
+
+                    self.skip_whitespace();
+                    if self.bump()? == b'}' {
+                        return Some(());
+                    }
+                }
+            }
+            b'[' => {
+                self.bump();
+                for index in 0.. {
+                    self.skip_whitespace();
+                    if self.peek()? == b']' {
+                        self.bump();
+                        return Some(());
+                    }
+
+                    let length = path.len();
+                    path.push_str(&format!("[{}]", index));
+                    self.value(path)?;
+                    path.truncate(length);
+
+                    self.skip_whitespace();
+                    if self.bump()? == b']' {
+                        return Some(());
+                    }
+                }
+                None
+            }
+            b'"' => self.string().map(drop),
+            _ => {
+                while !matches!(
+                    self.peek()?,
+                    b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n'
+                ) {
+                    self.bump();
+                }
+                Some(())
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.bump();
+        let start = self.index;
+        loop {
+            match self.bump()? {
+                b'"' => break,
+                b'\\' => {
+                    self.bump();
+                }
+                _ => {}
+            }
+        }
+        let raw = &self.json[start..self.index - 1];
+        Some(
+            match serde_json::from_slice(&self.json[start - 1..self.index]) {
+                Ok(string) => string,
+                Err(_) => String::from_utf8_lossy(raw).into_owned(),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = r#"{
+        "pageref": "page_1",
+        "startedDateTime": "2020-01-01T00:00:00.123+01:00",
+        "time": 1,
+        "request": {"method": "GET", "url": "http://example.com/", "httpVersion": "HTTP/1.1",
+            "cookies": [], "headers": [], "queryString": [], "headersSize": -1, "bodySize": 0},
+        "response": {"status": 200, "statusText": "OK", "httpVersion": "HTTP/1.1",
+            "cookies": [], "headers": [], "content": {"size": 5, "mimeType": "text/plain"},
+            "redirectURL": "", "headersSize": -1, "bodySize": 5},
+        "cache": {},
+        "timings": {"dns": -1, "send": 0, "wait": 1, "receive": 0}
+    }"#;
+
+    fn har(entries: &[String]) -> String {
+        format!(
+            r#"{{
+    "log": {{
+        "version": "1.2",
+        "creator": {{"name": "Creator?", "version": "0.1"}},
+        "pages": [{{"startedDateTime": "2020-01-01T00:00:00Z", "id": "page_1", "title": "", "pageTimings": {{}}}}],
+        "entries": [{}]
+    }}
+}}"#,
+            entries.join(",\n")
+        )
+    }
+
+    fn messages(json: &str) -> Vec<String> {
+        validate(json).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn it_accepts_valid_hars() {
+        assert_eq!(validate(&har(&[ENTRY.into(), ENTRY.into()])), vec![]);
+    }
+
+    #[test]
+    fn it_reports_syntax_errors() {
+        assert_eq!(
+            messages("{\n  \"log\": }"),
+            vec!["2:10: .: expected value at line 2 column 10"]
+        );
+    }
+
+    #[test]
+    fn it_reports_problems_with_positions() {
+        let broken = ENTRY
+            .replace("page_1", "page_2")
+            .replace("00.123+01:00", "00 +01:00")
+            .replace(
+                r#""headersSize": -1, "bodySize": 0"#,
+                r#""headersSize": -2, "bodySize": 0"#,
+            )
+            .replace(r#""dns": -1"#, r#""dns": -3"#)
+            .replace(r#""bodySize": 5"#, r#""bodySize": 7"#)
+            .replace(r#""cache": {},"#, "")
+            .replace(r#""wait": 1, "#, "");
+        assert_eq!(
+            messages(&har(&[ENTRY.into(), broken])),
+            vec![
+                "18:1: log.entries[1]: missing field `cache`",
+                "19:20: log.entries[1].pageref: no page with id \"page_2\"",
+                "20:28: log.entries[1].startedDateTime: \"2020-01-01T00:00:00 +01:00\" is not an ISO 8601 date and time",
+                "23:77: log.entries[1].request.headersSize: invalid value -2, use -1 when unknown",
+                "26:63: log.entries[1].response.bodySize: 7 doesn't match content.size 5 and compression 0",
+                "28:20: log.entries[1].timings: missing field `wait`",
+                "28:28: log.entries[1].timings.dns: invalid value -3, use -1 when unknown",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_fields_the_spec_requires() {
+        let json = har(&[ENTRY.replace(r#""mimeType": "text/plain""#, r#""text": """#)])
+            .replace(r#""version": "1.2","#, "")
+            .replace(r#""title": "", "#, "")
+            .replace(
+                r#""cache": {},"#,
+                r#""cache": {"afterRequest": {"eTag": "", "hitCount": 0}},"#,
+            );
+        assert_eq!(
+            messages(&json),
+            vec![
+                "2:12: log: missing field `version`",
+                "5:19: log.pages[0]: missing field `title`",
+                "13:54: log.entries[0].response.content: missing field `mimeType`",
+                "15:35: log.entries[0].cache.afterRequest: missing field `lastAccess`",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_broken_entries_and_carries_on() {
+        let broken = ENTRY.replace(r#""method": "GET", "#, "");
+        let typed = ENTRY
+            .replace("page_1", "page_3")
+            .replace(r#""status": 200"#, r#""status": 0"#);
+        let problems = messages(&har(&[broken, typed]));
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].ends_with("log.entries[0].request: missing field `method`"));
+        assert!(problems[1].ends_with("log.entries[1].pageref: no page with id \"page_3\""));
+        assert!(problems[2].ends_with("log.entries[1].response.status: Invalid status code 0"));
+    }
+}
@@ -0,0 +1,4 @@
+//! The HAR model harplay serves from, for reading, checking and writing HAR
+//! files: the raw `har::generic` one and the parsed `har::typed` one.
+
+pub mod har;
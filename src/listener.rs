@@ -0,0 +1,96 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use crate::req_resp::parse_origin;
+
+/// An address to serve on, and the recorded origin it serves if only one
+/// (parsed from `ADDR[=ORIGIN]`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub address: SocketAddr,
+    pub origin: Option<String>,
+}
+
+impl Listener {
+    /// Whether requests recorded for `origin` are this listener's alone;
+    /// WebSocket origins count as their HTTP counterparts
+    pub fn serves(&self, origin: &str) -> bool {
+        let http_origin = origin
+            .strip_prefix("ws")
+            .map(|rest| format!("http{}", rest));
+        self.origin.as_deref() == Some(http_origin.as_deref().unwrap_or(origin))
+    }
+
+    /// The origin clients reach this listener at, on this machine when
+    /// listening on every interface
+    pub fn local_origin(&self, scheme: &str) -> String {
+        if self.address.ip().is_unspecified() {
+            format!("{}://localhost:{}", scheme, self.address.port())
+        } else {
+            format!("{}://{}", scheme, self.address)
+        }
+    }
+}
+
+impl FromStr for Listener {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let address = parts.next().unwrap_or_default();
+        // `:PORT` is short for listening on localhost
+        let address = match address.strip_prefix(':') {
+            Some(port) => port
+                .parse()
+                .ok()
+                .map(|port| SocketAddr::from((Ipv4Addr::LOCALHOST, port))),
+            None => address.parse().ok(),
+        }
+        .ok_or("Expected ADDR[=ORIGIN], as in 127.0.0.1:3030=https://example.com")?;
+        let origin = parts.next().map(parse_origin).transpose()?;
+        Ok(Self { address, origin })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("https://api.example.com", true; "same origin")]
+    #[test_case("wss://api.example.com", true; "web socket origin")]
+    #[test_case("http://api.example.com", false; "other scheme")]
+    #[test_case("https://cdn.example.com", false; "other host")]
+    fn it_serves_its_origin(origin: &str, expected: bool) {
+        let listener: Listener = ":3030=https://api.example.com".parse().unwrap();
+        assert_eq!(listener.serves(origin), expected);
+        assert!(!":3031".parse::<Listener>().unwrap().serves(origin));
+    }
+
+    #[test_case("127.0.0.1:3030", "http://127.0.0.1:3030"; "loopback")]
+    #[test_case("[::1]:3030", "http://[::1]:3030"; "ipv6 loopback")]
+    #[test_case("0.0.0.0:3030", "http://localhost:3030"; "every interface")]
+    #[test_case("[::]:3030", "http://localhost:3030"; "every ipv6 interface")]
+    fn it_has_local_origins(s: &str, expected: &str) {
+        assert_eq!(
+            s.parse::<Listener>().unwrap().local_origin("http"),
+            expected
+        );
+    }
+
+    #[test_case("127.0.0.1:3030", Ok(("127.0.0.1:3030", None)); "address")]
+    #[test_case(":3031=https://cdn.example.com/", Ok(("127.0.0.1:3031", Some("https://cdn.example.com"))); "port and origin")]
+    #[test_case("[::1]:80=http://api.example.com:8080", Ok(("[::1]:80", Some("http://api.example.com:8080"))); "ipv6")]
+    #[test_case("localhost:3030", Err(()); "hostname")]
+    #[test_case(":3030=api.example.com", Err(()); "bare origin")]
+    fn it_parses_listeners(s: &str, expected: Result<(&str, Option<&str>), ()>) {
+        assert_eq!(
+            s.parse::<Listener>().map_err(|_| ()),
+            expected.map(|(address, origin)| Listener {
+                address: address.parse().unwrap(),
+                origin: origin.map(String::from),
+            })
+        );
+    }
+}
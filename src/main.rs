@@ -1,49 +1,619 @@
 mod cli_args;
+mod delivery;
 mod errors;
-mod har;
+mod faults;
+mod listener;
 mod logging;
+mod proxy;
+mod record;
+mod reload;
 mod req_resp;
+mod tls;
 
+use std::collections::BTreeSet;
 use std::convert::TryInto;
-use std::sync::{Arc, Mutex};
+use std::future::Future;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
 
+use futures_util::future::{self, FutureExt};
 use hyper::{
+    server::conn::Http,
     service::{make_service_fn, service_fn},
     Body as HttpBody, Error as HttpError, Request as HttpRequest, Response as HttpResponse, Server,
+    Version,
 };
 use tokio::runtime::Runtime;
+use url::Url;
 
-use crate::cli_args::CliArgs;
+use harplay::har;
+
+use crate::cli_args::{CliArgs, Command};
+use crate::delivery::{is_upgrade, Delivery, Streaming};
 use crate::errors::*;
-use crate::req_resp::{HarResponder, InMemoryResponder, Request, ResponderBehaviour, Response};
+use crate::faults::{Fault, FaultInjector};
+use crate::proxy::{Handler, Proxy};
+use crate::record::{remove_hop_by_hop, Passthrough, Recording, Upstream};
+use crate::reload::Requests;
+use crate::req_resp::{
+    fill_e_tag_from_cache, web_socket_messages, BodyRewriter, ConditionalResponder, CorsConfig,
+    CorsResponder, HarResponder, HeadResponder, InMemoryResponder, RangeResponder, Request,
+    ResponderBehaviour, ResponderError, Response, RewriteResponder, SpillStore, Timings,
+};
+
+/// Responders stacked up following the command line options
+type DynResponder = Box<dyn HarResponder + Send>;
+
+/// How often to look for changes in the HAR files when watching them
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
 
-async fn respond<T>(
-    http_request: HttpRequest<T>,
+async fn respond(
+    http_request: HttpRequest<HttpBody>,
     responder: Arc<Mutex<impl HarResponder>>,
-) -> Result<HttpResponse<HttpBody>, HttpError> {
-    let request = match http_request.try_into().context(IncomingUrl) {
+    delivery: Arc<Delivery>,
+    faults: Arc<Mutex<FaultInjector>>,
+    passthrough: Option<Arc<Passthrough>>,
+    args: Arc<CliArgs>,
+    origin: Option<Arc<str>>,
+) -> Result<HttpResponse<HttpBody>, AppError> {
+    // The body is only kept around for upgrading to WebSockets, or passing the
+    // request through
+    let (parts, http_body) = http_request.into_parts();
+    let head = passthrough.as_ref().map(|_| request_head(&parts));
+    let version = parts.version;
+    let mut request: Request = match HttpRequest::from_parts(parts, ())
+        .try_into()
+        .context(IncomingUrl)
+    {
         Ok(request) => request,
         Err(error) => return Ok(error.into()),
     };
+    if let Some(origin) = &origin {
+        if let Err(error) = request.scope_to(origin).context(IncomingUrl) {
+            return Ok(error.into());
+        }
+        // What the request is for, rather than the listener it came in through
+        request.origin = Some(origin.to_string());
+    }
+    log::debug!("{} over {:?}", request, version);
+
+    let fault = match faults.lock() {
+        Ok(mut faults) => faults.roll(&request),
+        Err(_) => return Ok(AppError::DatabaseLock.into()),
+    };
+
+    if let Some(fault) = &fault {
+        log::debug!("Injecting {:?} into {}", fault, request);
+    }
+
+    match fault {
+        Some(Fault::Status(status_code)) => {
+            return Ok(Response {
+                status_code,
+                headers: Vec::new(),
+                body: None,
+                timings: Timings::default(),
+                web_socket_messages: Vec::new(),
+            }
+            .into())
+        }
+        Some(Fault::Reset) => return Err(AppError::InjectedReset),
+        Some(Fault::Delay(delay)) => tokio::time::delay_for(delay).await,
+        _ => {}
+    }
 
-    let mut responder = match responder.lock() {
-        Ok(lock) => lock,
+    let response = match responder.lock() {
+        Ok(mut responder) if fault == Some(Fault::WrongVariant) => {
+            responder.respond_to_other(&request)
+        }
+        Ok(mut responder) => responder.respond_to(&request),
         Err(_) => return Ok(AppError::DatabaseLock.into()),
     };
 
-    Ok(responder
-        .respond_to(&request)
-        .map(HttpResponse::<HttpBody>::from)
-        .map_err(AppError::from)
-        .unwrap_or_else(HttpResponse::<HttpBody>::from))
+    let (response, http_body) = match (response, head, &passthrough) {
+        (Err(ResponderError::RequestNotFound), Some(head), Some(passthrough)) => {
+            let http_request = HttpRequest::from_parts(head, http_body);
+            match pass_through(http_request, &request, passthrough, &responder, &args).await {
+                Ok(response) => (Ok(response), None),
+                Err(http_response) => return Ok(http_response),
+            }
+        }
+        (response, _, _) => (response, Some(http_body)),
+    };
+
+    let mut http_response = match (response, http_body) {
+        (Ok(response), _) if fault == Some(Fault::Truncate) => {
+            delivery.deliver_truncated(&request, response).await
+        }
+        (Ok(response), Some(http_body))
+            if !response.web_socket_messages.is_empty() && is_upgrade(&request) =>
+        {
+            delivery.deliver_web_socket(&request, response, http_body.on_upgrade())
+        }
+        (Ok(response), _) => delivery.deliver(&request, response).await,
+        (Err(error), _) => AppError::from(error).into(),
+    };
+
+    // Recorded HTTP/1 headers like `Connection` are illegal in HTTP/2
+    if version == Version::HTTP_2 {
+        remove_hop_by_hop(http_response.headers_mut());
+    }
+    Ok(http_response)
+}
+
+/// Answer with what the passthrough upstream does, through the same responders
+/// as replays, and keep the exchange when recording missing requests. Fails
+/// with what to answer instead.
+async fn pass_through(
+    http_request: HttpRequest<HttpBody>,
+    request: &Request,
+    passthrough: &Passthrough,
+    responder: &Mutex<impl HarResponder>,
+    args: &CliArgs,
+) -> Result<Response, HttpResponse<HttpBody>> {
+    let entry = match passthrough.upstream.forward(http_request).await {
+        Ok((_, entry)) => entry,
+        Err(error) => {
+            log::error!("Passing {} through: {}", request, error);
+            return Err(bad_gateway());
+        }
+    };
+    log::info!("Passed {} through ({})", request, entry.response.status);
+
+    passed_through(
+        entry,
+        request,
+        passthrough.recording.as_deref(),
+        responder,
+        args,
+    )
+    .map_err(|error| {
+        log::error!("Passing {} through: {}", request, error);
+        error.into()
+    })
+}
+
+/// Answer `request` with the `entry` an upstream answered it with, as replays
+/// of it will be. It's recorded along the replayed responses when recording,
+/// and only goes through wrappers like the ones around them otherwise.
+fn passed_through(
+    entry: har::Entries,
+    request: &Request,
+    recording: Option<&Recording>,
+    responder: &Mutex<impl HarResponder>,
+    args: &CliArgs,
+) -> Result<Response, AppError> {
+    let mut recorded = Response::from(entry.response.clone());
+    recorded.timings = Timings::from(&entry);
+
+    let response = match recording {
+        Some(recording) => {
+            let response = {
+                let mut responder = responder.lock().map_err(|_| AppError::DatabaseLock)?;
+                // Kept under the request as it came in, the entry has the upstream's URL
+                responder.record(request.clone(), recorded);
+                responder.respond_to(request)?
+            };
+            recording.append(entry)?;
+            response
+        }
+        None => {
+            let mut once = wrap_responder(
+                args,
+                Box::new(InMemoryResponder::empty(ResponderBehaviour::AlwaysFirst)),
+            );
+            once.record(request.clone(), recorded);
+            once.respond_to(request)?
+        }
+    };
+
+    // Waiting for the upstream took its time already
+    Ok(Response {
+        timings: Timings::default(),
+        ..response
+    })
+}
+
+async fn record(
+    http_request: HttpRequest<HttpBody>,
+    upstream: Arc<Upstream>,
+    recording: Arc<Recording>,
+) -> Result<HttpResponse<HttpBody>, HttpError> {
+    let method = http_request.method().clone();
+    let uri = http_request.uri().clone();
+
+    Ok(match upstream.forward(http_request).await {
+        Ok((response, entry)) => {
+            log::info!("Recorded {} {} ({})", method, uri, response.status());
+            if let Err(error) = recording.append(entry) {
+                log::error!("{}", error);
+            }
+            response
+        }
+        Err(error) => {
+            log::error!("{} {}: {}", method, uri, error);
+            bad_gateway()
+        }
+    })
+}
+
+fn bad_gateway() -> HttpResponse<HttpBody> {
+    HttpResponse::builder()
+        .status(http::StatusCode::BAD_GATEWAY)
+        .body(HttpBody::empty())
+        .unwrap()
+}
+
+/// A copy of the request line and headers, since `Parts` can't be cloned
+fn request_head(parts: &http::request::Parts) -> http::request::Parts {
+    let (mut head, ()) = HttpRequest::new(()).into_parts();
+    head.method = parts.method.clone();
+    head.uri = parts.uri.clone();
+    head.version = parts.version;
+    head.headers = parts.headers.clone();
+    head
+}
+
+/// Build the body rewriter out of the command line options and the recorded origins
+fn body_rewriter(args: &CliArgs, origins: &BTreeSet<String>) -> BodyRewriter {
+    let tls = args.tls_cert.is_some() || args.tls_self_signed;
+    let (scheme, web_socket_scheme) = if tls {
+        ("https", "wss")
+    } else {
+        ("http", "ws")
+    };
+    // Origins go to the listener serving them, or to the first one
+    let local_origin = |origin: &str| {
+        let web_socket = origin.starts_with("ws");
+        let listener = args
+            .network_bind
+            .iter()
+            .find(|listener| listener.serves(origin));
+        // The first listener may be reachable at some other origin
+        if let (None, Some(public_origin)) = (listener, &args.public_origin) {
+            return if web_socket {
+                public_origin.replacen("http", "ws", 1)
+            } else {
+                public_origin.clone()
+            };
+        }
+        let listener = listener.unwrap_or(&args.network_bind[0]);
+        if web_socket {
+            listener.local_origin(web_socket_scheme)
+        } else {
+            listener.local_origin(scheme)
+        }
+    };
+    let mut rewriter = BodyRewriter::new();
+
+    for rewrite in args.rewrite_origin.iter() {
+        let target = rewrite
+            .target
+            .clone()
+            .unwrap_or_else(|| local_origin(&rewrite.origin));
+        log::trace!(
+            "Rewriting {} to {} in response bodies",
+            rewrite.origin,
+            target
+        );
+        rewriter.add_origin(rewrite.origin.as_str(), target.as_str());
+    }
+
+    if args.rewrite_all_origins {
+        for origin in origins.iter() {
+            rewriter.add_origin(origin.as_str(), local_origin(origin).as_str());
+        }
+    }
+
+    rewriter
+}
+
+/// Turn a HAR entry into a request/response pair, unless filtered out or unusable
+fn load_entry(args: &CliArgs, entry: har::Entries) -> Option<(Request, Response)> {
+    if let Some(regex) = &args.url_filter {
+        if regex.is_match(&entry.request.url) {
+            log::trace!(
+                "Request excluded by filter: {} {}",
+                &entry.request.method,
+                &entry.request.url,
+            );
+            return None;
+        }
+    }
+
+    if args.skip_cached && entry.is_from_cache() {
+        log::trace!(
+            "Request excluded, served from the browser cache: {} {}",
+            &entry.request.method,
+            &entry.request.url,
+        );
+        return None;
+    }
+
+    if !args.resource_type.is_empty() {
+        let resource_type = entry.resource_type().unwrap_or_else(|| {
+            static UNTYPED: Once = Once::new();
+            UNTYPED.call_once(|| {
+                log::warn!("Leaving out entries without a resource type, which only Chrome records")
+            });
+            ""
+        });
+        if !args
+            .resource_type
+            .iter()
+            .any(|wanted| wanted.eq_ignore_ascii_case(resource_type))
+        {
+            log::trace!(
+                "Request excluded by resource type {:?}: {} {}",
+                resource_type,
+                &entry.request.method,
+                &entry.request.url,
+            );
+            return None;
+        }
+    }
+
+    let url = entry.request.url.clone();
+    let timings = Timings::from(&entry);
+    let mut req: Request = match entry.request.try_into() {
+        Ok(req) => {
+            log::trace!("Adding {}", req);
+            req
+        }
+        Err(error) => {
+            log::error!("Entry dropped: Error parsing URL {}: {:?}", url, error);
+            return None;
+        }
+    };
+    // Proxies tell every origin apart, otherwise requests to an origin with a
+    // listener of its own are that one's alone
+    let scope = if args.proxy {
+        req.origin.clone()
+    } else {
+        args.network_bind
+            .iter()
+            .find(|listener| {
+                req.origin
+                    .as_deref()
+                    .is_some_and(|origin| listener.serves(origin))
+            })
+            .and_then(|listener| listener.origin.clone())
+    };
+    if let Some(origin) = scope {
+        if let Err(error) = req.scope_to(&origin) {
+            log::error!(
+                "Entry dropped: Error scoping {} to {}: {:?}",
+                url,
+                origin,
+                error
+            );
+            return None;
+        }
+    }
+    let mut resp: Response = entry.response.into();
+    resp.timings = timings;
+    if let Some(messages) = &entry.web_socket_messages {
+        resp.web_socket_messages = web_socket_messages(messages);
+    }
+    if args.conditional {
+        fill_e_tag_from_cache(&mut resp, &entry.cache);
+    }
+    Some((req, resp))
+}
+
+/// Load the HAR files and stack up the responders the options ask for. Also
+/// returns the loaded requests, to tell reloads apart.
+fn load_responder(args: &CliArgs) -> Result<(DynResponder, Requests), Box<dyn std::error::Error>> {
+    let mut in_memory =
+        InMemoryResponder::empty(args.behaviour.clone()).with_duplicates(args.duplicates.clone());
+    if let Some(threshold) = args.spill_bodies_over {
+        log::trace!("Keeping bodies over {} bytes on disk", threshold);
+        in_memory = in_memory.with_spill_store(SpillStore::new(threshold)?);
+    }
+
+    let har_files = har::find_files(&args.har_files)?;
+    if har_files.is_empty() {
+        return Err("No HAR files found".into());
+    }
+
+    let mut origins = BTreeSet::new();
+    let mut requests = Requests::new();
+    for path in har_files.iter() {
+        log::trace!("Loading requests from {:?}", path);
+        in_memory.next_source();
+
+        let har_file = har::open(path, args.zip_entry.as_deref())?;
+        let on_entry = |entry| {
+            if let Some((req, resp)) = load_entry(args, entry) {
+                let request = format!("{} {}", req.method, req.original_url);
+                reload::add_request(&mut requests, request, &resp);
+                if let Some(origin) = &req.origin {
+                    if !origins.contains(origin) {
+                        origins.insert(origin.clone());
+                    }
+                }
+                in_memory.insert(req, resp);
+            }
+        };
+        let count = if args.lenient {
+            har::stream_from_reader_lenient(har_file, on_entry, |diagnostic| {
+                log::warn!("{:?}: {}", path, diagnostic)
+            })?
+        } else {
+            har::stream_from_reader(har_file, on_entry)?
+        };
+        log::trace!("Read {} entries from {:?}", count, path);
+    }
+
+    // Only now are all the origins to rewrite known
+    let rewriter = body_rewriter(args, &origins);
+    let responder: DynResponder = if rewriter.is_empty() {
+        Box::new(in_memory)
+    } else {
+        in_memory.rewrite_bodies(&rewriter);
+        Box::new(RewriteResponder::new(in_memory, rewriter))
+    };
+
+    if args.conditional {
+        log::trace!("Conditional requests enabled");
+    }
+    if args.cors {
+        log::trace!("CORS mode enabled");
+    }
+
+    Ok((wrap_responder(args, responder), requests))
+}
+
+/// Wrap the recorded responses in the responders the options ask for
+fn wrap_responder(args: &CliArgs, mut responder: DynResponder) -> DynResponder {
+    responder = Box::new(HeadResponder::new(responder));
+
+    if args.conditional {
+        responder = Box::new(ConditionalResponder::new(responder));
+    }
+
+    responder = Box::new(RangeResponder::new(responder));
+
+    if args.cors {
+        responder = Box::new(CorsResponder::new(
+            responder,
+            CorsConfig {
+                allowed_origins: args.cors_origin.clone(),
+                allowed_methods: args.cors_method.clone(),
+                allowed_headers: args.cors_header.clone(),
+                allow_credentials: args.cors_credentials,
+                max_age: args.cors_max_age,
+            },
+        ));
+    }
+
+    responder
+}
+
+/// Swap the responder for one loaded afresh. Sequences start over, since
+/// responders keep where they are in them along with the responses.
+fn reload_responder(args: &CliArgs, responder: &Mutex<DynResponder>, requests: &mut Requests) {
+    match load_responder(args) {
+        Ok((reloaded, reloaded_requests)) => {
+            match responder.lock() {
+                Ok(mut responder) => *responder = reloaded,
+                Err(_) => {
+                    log::error!("{}", AppError::DatabaseLock);
+                    return;
+                }
+            }
+            reload::log_diff(requests, &reloaded_requests);
+            *requests = reloaded_requests;
+        }
+        Err(error) => log::error!("Keeping the previous responses, reload failed: {}", error),
+    }
+}
+
+/// TLS setup following the command line options, if serving HTTPS at all
+fn tls_config(args: &CliArgs) -> Result<Option<rustls::ServerConfig>, AppError> {
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        log::info!("Serving HTTPS with the certificate in {:?}", cert);
+        return tls::pem_config(cert, key).map(Some);
+    }
+
+    if args.tls_self_signed {
+        let hostnames = if args.tls_hostname.is_empty() {
+            let mut hostnames = vec!["localhost".to_string()];
+            for listener in args.network_bind.iter() {
+                let address = listener.address.ip().to_string();
+                if !hostnames.contains(&address) {
+                    hostnames.push(address);
+                }
+            }
+            hostnames
+        } else {
+            args.tls_hostname.clone()
+        };
+        log::info!(
+            "Serving HTTPS with a self-signed certificate for {:?}",
+            hostnames
+        );
+        return tls::self_signed_config(&hostnames).map(Some);
+    }
+
+    Ok(None)
+}
+
+/// Serve whatever `upstream` answers, recording it all to `output`
+fn run_recorder(
+    upstream: &Url,
+    output: &Path,
+    network_bind: &SocketAddr,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log::info!("Recording {} to {:?}", upstream, output);
+    let upstream = Arc::new(Upstream::new(upstream.clone()));
+    let recording = Arc::new(Recording::open(output.to_path_buf())?);
+
+    let saved = recording.clone();
+    let service = make_service_fn(move |_| {
+        let upstream = upstream.clone();
+        let recording = recording.clone();
+
+        async {
+            Ok::<_, HttpError>(service_fn(move |request| {
+                record(request, upstream.clone(), recording.clone())
+            }))
+        }
+    });
+
+    // Bound from within the runtime
+    Runtime::new()?
+        .block_on(async { saved.serve(Server::bind(network_bind).serve(service)).await })?;
+
+    Ok(())
+}
+
+/// Check HAR files against the spec, printing every problem found as
+/// `FILE:LINE:COLUMN: PATH: MESSAGE`, and fail if there are any
+fn validate(
+    har_files: &[PathBuf],
+    zip_entry: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut count = 0;
+    for path in har::find_files(har_files)? {
+        let mut json = String::new();
+        har::open(&path, zip_entry)?.read_to_string(&mut json)?;
+
+        for problem in har::validate::validate(&json) {
+            println!("{}:{}", path.display(), problem);
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        return Err(format!("{} problems found", count).into());
+    }
+    Ok(())
 }
 
 #[paw::main]
 fn main(args: CliArgs) -> Result<(), Box<dyn std::error::Error>> {
     logging::setup_logging(args.log_level)?;
 
+    match &args.command {
+        Some(Command::Validate {
+            har_files,
+            zip_entry,
+        }) => return validate(har_files, zip_entry.as_deref()),
+        Some(Command::Record {
+            output,
+            upstream,
+            network_bind,
+        }) => return run_recorder(upstream, output, network_bind),
+        None => {}
+    }
+
+    let args = Arc::new(args);
+
     log::trace!("{} {}", "harPlay", env!("CARGO_PKG_VERSION"));
-    log::trace!("Loading requests from {:?}", args.har_file);
 
     if let Some(regex) = &args.url_filter {
         log::trace!("URL filtering by {:?}", regex);
@@ -51,64 +621,349 @@ fn main(args: CliArgs) -> Result<(), Box<dyn std::error::Error>> {
         log::trace!("URL filtering disabled");
     }
 
-    let responder = Arc::new(Mutex::new({
-        let har_file = har::from_path(&args.har_file)?;
-
-        InMemoryResponder::new(
-            ResponderBehaviour::SequentialWrapping,
-            har_file
-                .log
-                .entries
-                .into_iter()
-                .filter(|entry| {
-                    args.url_filter
-                        .as_ref()
-                        .map(|regex| {
-                            let is_match = !regex.is_match(&entry.request.url);
-
-                            if !is_match {
-                                log::trace!(
-                                    "Request excluded by filter: {} {}",
-                                    &entry.request.method,
-                                    &entry.request.url,
-                                );
-                            }
-
-                            is_match
-                        })
-                        .unwrap_or(true)
-                })
-                .filter_map(|entry| {
-                    let url = entry.request.url.clone();
-                    let req: Request = match entry.request.try_into() {
-                        Ok(req) => {
-                            log::trace!("Adding {}", req);
-                            req
-                        }
-                        Err(error) => {
-                            log::error!("Entry dropped: Error parsing URL {}: {:?}", url, error);
-                            return None;
-                        }
-                    };
-                    let resp: Response = entry.response.into();
-                    Some((req, resp))
-                }),
-        )
-    }));
+    let (responder, requests) = load_responder(&args)?;
+    let responder = Arc::new(Mutex::new(responder));
 
-    let service = make_service_fn(move |_| {
+    if args.watch {
+        log::info!("Watching {:?} for changes", args.har_files);
+        let args = args.clone();
         let responder = responder.clone();
+        let mut requests = requests;
 
-        async {
-            Ok::<_, HttpError>(service_fn(move |request| {
-                respond(request, responder.clone())
+        reload::watch(args.har_files.clone(), WATCH_INTERVAL, move || {
+            reload_responder(&args, &responder, &mut requests)
+        });
+    }
+
+    let delivery = Arc::new({
+        let mut delivery = Delivery::new();
+
+        if args.latency || args.latency_scale.is_some() {
+            let scale = args.latency_scale.unwrap_or(1.0);
+            log::trace!("Replaying recorded latency, scaled by {}", scale);
+            delivery = delivery.with_latency(scale);
+        }
+
+        if let Some(throttle) = &args.throttle {
+            log::trace!("Throttling responses to {:?}", throttle);
+            delivery = delivery.with_throttle(throttle.clone());
+        }
+
+        if let Some(interval) = args.stream_interval {
+            log::trace!("Streaming responses, {}ms apart", interval);
+            delivery =
+                delivery.with_streaming(Streaming::Interval(Duration::from_millis(interval)));
+        } else if args.stream {
+            log::trace!("Streaming responses following their recorded timings");
+            delivery = delivery.with_streaming(Streaming::Recorded);
+        }
+
+        if args.ws_wait_for_client {
+            log::trace!("WebSocket replays wait for the recorded client frames");
+            delivery = delivery.with_web_socket_wait();
+        }
+
+        for url_throttle in args.throttle_url.iter() {
+            log::trace!(
+                "Throttling responses to {:?} to {:?}",
+                url_throttle.pattern,
+                url_throttle.throttle
+            );
+            delivery = delivery.with_url_throttle(url_throttle.clone());
+        }
+
+        delivery
+    });
+
+    let faults = Arc::new(Mutex::new({
+        let faults = FaultInjector::new(args.fault.clone(), args.fault_seed);
+
+        if !faults.is_empty() {
+            log::warn!("Fault injection enabled");
+        }
+
+        faults
+    }));
+
+    let passthrough = match &args.passthrough {
+        Some(upstream) => {
+            log::info!(
+                "Passing requests missing from the HAR files to {}",
+                upstream
+            );
+            let recording = match &args.record_missing {
+                Some(path) => {
+                    log::info!("Recording passed through requests to {:?}", path);
+                    Some(Arc::new(Recording::open(path.clone())?))
+                }
+                None => None,
+            };
+            Some(Arc::new(Passthrough {
+                upstream: Upstream::new(upstream.clone()),
+                recording,
             }))
         }
+        None => None,
+    };
+
+    let recording = passthrough
+        .as_ref()
+        .and_then(|passthrough| passthrough.recording.clone());
+    let tls_config = tls_config(&args)?;
+
+    // Every request ends up here, whichever listener or proxy tunnel it came through
+    let handler_args = args.clone();
+    let handler: Handler = Arc::new(move |request, origin| {
+        respond(
+            request,
+            responder.clone(),
+            delivery.clone(),
+            faults.clone(),
+            passthrough.clone(),
+            handler_args.clone(),
+            origin,
+        )
+        .boxed()
     });
 
-    Runtime::new()?.block_on(async {
-        Server::bind(&args.network_bind).serve(service).await
-    })?;
+    let proxy = if args.proxy {
+        let ca_directory = args
+            .proxy_ca
+            .clone()
+            .unwrap_or_else(tls::default_ca_directory);
+        log::info!("Working as a proxy, with the CA in {:?}", ca_directory);
+        let ca = tls::CertificateAuthority::open(&ca_directory)?;
+        Some(Arc::new(Proxy::new(ca, handler.clone())))
+    } else {
+        None
+    };
+
+    let new_service = move |origin: Option<Arc<str>>| {
+        let handler = handler.clone();
+        let proxy = proxy.clone();
+
+        // Services are made per connection, whose first request tells which
+        // version of HTTP was negotiated for all of them
+        let mut announced = false;
+        service_fn(move |request: HttpRequest<HttpBody>| {
+            if !announced {
+                announced = true;
+                log::info!("New connection over {:?}", request.version());
+            }
+            match &proxy {
+                Some(proxy) => proxy.handle(request),
+                None => handler(request, origin.clone()),
+            }
+        })
+    };
+
+    for listener in args.network_bind.iter() {
+        match &listener.origin {
+            Some(origin) => log::info!("Serving {} on {}", origin, listener.address),
+            None => log::info!("Serving on {}", listener.address),
+        }
+    }
+
+    // Listeners are bound from within the runtime, all of them sharing the
+    // same responder
+    let mut runtime = Runtime::new()?;
+    match tls_config {
+        Some(tls_config) => {
+            let mut http = Http::new();
+            http.http2_max_concurrent_streams(args.http2_max_concurrent_streams);
+            runtime.block_on(until_done(recording, async {
+                future::try_join_all(args.network_bind.iter().map(|listener| {
+                    let new_service = new_service.clone();
+                    let origin = listener.origin.as_deref().map(Arc::from);
+                    tls::serve(
+                        &listener.address,
+                        tls_config.clone(),
+                        http.clone(),
+                        move || new_service(origin.clone()),
+                    )
+                }))
+                .await
+                .map(drop)
+            }))?;
+        }
+        None => {
+            runtime.block_on(until_done(recording, async {
+                future::try_join_all(args.network_bind.iter().map(|listener| {
+                    let new_service = new_service.clone();
+                    let origin: Option<Arc<str>> = listener.origin.as_deref().map(Arc::from);
+                    let service = make_service_fn(move |_| {
+                        let service = new_service(origin.clone());
+                        async { Ok::<_, HttpError>(service) }
+                    });
+                    // HTTP/2 clients with prior knowledge are told apart by their preface
+                    Server::bind(&listener.address)
+                        .http2_max_concurrent_streams(args.http2_max_concurrent_streams)
+                        .serve(service)
+                }))
+                .await
+                .map(drop)
+            }))?;
+        }
+    }
 
     Ok(())
 }
+
+/// Run `serve`, saving the recording of passed through requests along the
+/// way when there's one
+async fn until_done<F, E>(recording: Option<Arc<Recording>>, serve: F) -> Result<(), E>
+where
+    F: Future<Output = Result<(), E>>,
+{
+    match recording {
+        Some(recording) => recording.serve(serve).await,
+        None => serve.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use assert_matches::assert_matches;
+    use structopt::StructOpt;
+    use test_case::test_case;
+    use url::Url;
+
+    use super::*;
+    use crate::req_resp::Header;
+
+    fn entry(url: &str, body: &str) -> String {
+        format!(
+            r#"{{"startedDateTime":"2020-01-01T00:00:00Z","time":1,"request":{{"method":"GET","url":"{}","httpVersion":"HTTP/1.1","cookies":[],"headers":[],"queryString":[],"headersSize":-1,"bodySize":0}},"response":{{"status":200,"statusText":"OK","httpVersion":"HTTP/1.1","cookies":[],"headers":[],"content":{{"size":0,"mimeType":"text/plain","text":"{}"}},"redirectURL":"","headersSize":-1,"bodySize":0}},"cache":{{}},"timings":{{"send":0,"wait":1,"receive":0}}}}"#,
+            url, body
+        )
+    }
+
+    fn request(path: &str) -> Request {
+        let url = format!("http://harplay{}", path);
+        Request {
+            method: "GET".into(),
+            url: Url::parse(&url).unwrap(),
+            original_url: url,
+            origin: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn body(responder: &Mutex<DynResponder>, path: &str) -> Option<Vec<u8>> {
+        responder
+            .lock()
+            .unwrap()
+            .respond_to(&request(path))
+            .ok()
+            .and_then(|response| response.body)
+    }
+
+    #[test]
+    fn recording_goes_through_every_wrapper() {
+        let directory = tempfile::tempdir().unwrap();
+        let har = directory.path().join("a.har");
+        let entries = entry("https://api.example.com/a", "recorded");
+        fs::write(&har, format!(r#"{{"log":{{"entries":[{}]}}}}"#, entries)).unwrap();
+        let args = CliArgs::from_iter(&[
+            "harplay".as_ref(),
+            har.as_os_str(),
+            "--rewrite-all-origins".as_ref(),
+            "--conditional".as_ref(),
+            "--cors".as_ref(),
+        ]);
+
+        let (responder, _) = load_responder(&args).unwrap();
+        let responder = Mutex::new(responder);
+        assert_eq!(body(&responder, "/b"), None);
+
+        let passed_through = Response {
+            status_code: 200,
+            headers: Vec::new(),
+            body: Some(b"passed through".to_vec()),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        };
+        responder
+            .lock()
+            .unwrap()
+            .record(request("/b"), passed_through);
+        assert_eq!(body(&responder, "/b"), Some(b"passed through".to_vec()));
+    }
+
+    #[test_case(false; "passing through")]
+    #[test_case(true; "recording missing ones")]
+    fn passed_through_responses_go_through_every_wrapper(record_missing: bool) {
+        let directory = tempfile::tempdir().unwrap();
+        let har = directory.path().join("a.har");
+        let entries = entry("https://api.example.com/a", "recorded");
+        fs::write(&har, format!(r#"{{"log":{{"entries":[{}]}}}}"#, entries)).unwrap();
+        let recorded = directory.path().join("missing.har");
+        let mut arguments = vec![
+            "harplay".as_ref(),
+            har.as_os_str(),
+            "--cors".as_ref(),
+            "--passthrough".as_ref(),
+            "https://api.example.com".as_ref(),
+        ];
+        if record_missing {
+            arguments.extend(&["--record-missing".as_ref(), recorded.as_os_str()]);
+        }
+        let args = CliArgs::from_iter(&arguments);
+        let recording = args
+            .record_missing
+            .as_ref()
+            .map(|path| Recording::open(path.clone()).unwrap());
+
+        let (responder, _) = load_responder(&args).unwrap();
+        let responder = Mutex::new(responder);
+        let mut request = request("/b");
+        request.headers.push(Header {
+            name: "Origin".into(),
+            value: "http://localhost:8080".into(),
+        });
+        let upstream: har::Entries =
+            serde_json::from_str(&entry("https://api.example.com/b", "passed through")).unwrap();
+
+        let response =
+            passed_through(upstream, &request, recording.as_ref(), &responder, &args).unwrap();
+        assert_eq!(response.body, Some(b"passed through".to_vec()));
+        assert_eq!(response.header("access-control-allow-origin"), Some("*"));
+        assert_eq!(response.timings, Timings::default());
+
+        let replayed = responder.lock().unwrap().respond_to(&request);
+        if record_missing {
+            let replayed = replayed.unwrap();
+            assert_eq!(replayed.body, response.body);
+            assert_eq!(replayed.header("access-control-allow-origin"), Some("*"));
+        } else {
+            assert_matches!(replayed, Err(ResponderError::RequestNotFound));
+        }
+    }
+
+    #[test]
+    fn reloading_restarts_sequences() {
+        let directory = tempfile::tempdir().unwrap();
+        let har = directory.path().join("a.har");
+        let entries = [
+            entry("https://api.example.com/a", "first"),
+            entry("https://api.example.com/a", "second"),
+        ];
+        fs::write(
+            &har,
+            format!(r#"{{"log":{{"entries":[{}]}}}}"#, entries.join(",")),
+        )
+        .unwrap();
+        let args = CliArgs::from_iter(&["harplay".as_ref(), har.as_os_str()]);
+
+        let (responder, mut requests) = load_responder(&args).unwrap();
+        let responder = Mutex::new(responder);
+        assert_eq!(body(&responder, "/a"), Some("first".into()));
+
+        reload_responder(&args, &responder, &mut requests);
+        assert_eq!(body(&responder, "/a"), Some("first".into()));
+        assert_eq!(body(&responder, "/a"), Some("second".into()));
+        assert_eq!(body(&responder, "/a"), Some("first".into()));
+    }
+}
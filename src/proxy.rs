@@ -0,0 +1,201 @@
+//! Forward proxying: clients send every request through harplay, plain HTTP
+//! ones with absolute URLs and HTTPS ones through `CONNECT` tunnels, which get
+//! intercepted with certificates for the hosts they are for.
+
+use std::sync::Arc;
+
+use futures_util::future::{self, BoxFuture, FutureExt};
+use hyper::{
+    server::conn::Http, service::service_fn, Body as HttpBody, Method, Request as HttpRequest,
+    Response as HttpResponse, StatusCode, Uri,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::errors::*;
+use crate::req_resp::parse_origin;
+use crate::tls::{self, CertificateAuthority};
+
+/// Answers requests, scoped to the origin they are for if known
+pub type Handler = Arc<
+    dyn Fn(
+            HttpRequest<HttpBody>,
+            Option<Arc<str>>,
+        ) -> BoxFuture<'static, Result<HttpResponse<HttpBody>, AppError>>
+        + Send
+        + Sync,
+>;
+
+pub struct Proxy {
+    ca: CertificateAuthority,
+    handler: Handler,
+}
+
+impl Proxy {
+    pub fn new(ca: CertificateAuthority, handler: Handler) -> Self {
+        Self { ca, handler }
+    }
+
+    pub fn handle(
+        &self,
+        request: HttpRequest<HttpBody>,
+    ) -> BoxFuture<'static, Result<HttpResponse<HttpBody>, AppError>> {
+        if request.method() == Method::CONNECT {
+            return future::ready(Ok(self.connect(request))).boxed();
+        }
+
+        // Requests to the proxy itself have no origin, and aren't scoped
+        let origin = origin(request.uri(), "http");
+        (self.handler)(request, origin)
+    }
+
+    /// Agree to open the tunnel, and intercept it as the host it is for once
+    /// the client starts talking TLS through it
+    fn connect(&self, request: HttpRequest<HttpBody>) -> HttpResponse<HttpBody> {
+        let authority = match request.uri().authority() {
+            Some(authority) => authority.clone(),
+            None => return status(StatusCode::BAD_REQUEST),
+        };
+        let config = match self.ca.server_config(authority.host()) {
+            Ok(config) => config,
+            Err(error) => {
+                log::error!("Not intercepting {}: {}", authority, error);
+                return status(StatusCode::BAD_GATEWAY);
+            }
+        };
+        let origin = origin(request.uri(), "https");
+        let handler = self.handler.clone();
+        log::debug!("Intercepting a tunnel to {}", authority);
+
+        tokio::spawn(async move {
+            let upgraded = match request.into_body().on_upgrade().await {
+                Ok(upgraded) => upgraded,
+                Err(error) => {
+                    log::warn!("Tunnel to {} failed: {}", authority, error);
+                    return;
+                }
+            };
+
+            match TlsAcceptor::from(config).accept(upgraded).await {
+                Ok(stream) => {
+                    let service = service_fn(move |request| handler(request, origin.clone()));
+                    tls::serve_connection(stream, Http::new(), service, &authority).await
+                }
+                Err(error) => log::warn!(
+                    "TLS handshake through the tunnel to {} failed, does the client trust the CA? {}",
+                    authority,
+                    error
+                ),
+            }
+        });
+
+        HttpResponse::new(HttpBody::empty())
+    }
+}
+
+/// The origin of `uri`, absolute in proxy requests; tunnels only carry the
+/// authority, and requests inside them not even that (under HTTP/1)
+fn origin(uri: &Uri, default_scheme: &str) -> Option<Arc<str>> {
+    let scheme = uri.scheme_str().unwrap_or(default_scheme);
+    let origin = format!("{}://{}", scheme, uri.authority()?);
+    parse_origin(&origin).ok().map(Arc::from)
+}
+
+fn status(status: StatusCode) -> HttpResponse<HttpBody> {
+    HttpResponse::builder()
+        .status(status)
+        .body(HttpBody::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("http://api.example.com/a?b=c", "http", Some("http://api.example.com"); "absolute")]
+    #[test_case("http://api.example.com:80/a", "http", Some("http://api.example.com"); "default port")]
+    #[test_case("api.example.com:443", "https", Some("https://api.example.com"); "tunnel")]
+    #[test_case("api.example.com:8443", "https", Some("https://api.example.com:8443"); "tunnel port")]
+    #[test_case("/a?b=c", "https", None; "relative")]
+    fn it_finds_origins(uri: &str, default_scheme: &str, expected: Option<&str>) {
+        assert_eq!(
+            origin(&uri.parse().unwrap(), default_scheme).as_deref(),
+            expected
+        );
+    }
+
+    #[test]
+    fn it_intercepts_tunnels() {
+        use hyper::client::conn::Builder;
+        use rustls::ClientConfig;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+        use tokio::runtime::Runtime;
+        use tokio_rustls::TlsConnector;
+
+        let directory = tempfile::tempdir().unwrap();
+        let ca = CertificateAuthority::open(directory.path()).unwrap();
+        // Answers with the origin requests are scoped to
+        let handler: Handler = Arc::new(|_, origin: Option<Arc<str>>| {
+            let body = origin.as_deref().unwrap_or("none").to_owned();
+            future::ready(Ok(HttpResponse::new(HttpBody::from(body)))).boxed()
+        });
+        let proxy = Arc::new(Proxy::new(ca, handler));
+
+        Runtime::new().unwrap().block_on(async {
+            let mut listener =
+                TcpListener::from_std(std::net::TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service_fn(move |request| proxy.handle(request));
+                let _ = Http::new()
+                    .serve_connection(stream, service)
+                    .with_upgrades()
+                    .await;
+            });
+
+            let mut stream =
+                TcpStream::from_std(std::net::TcpStream::connect(address).unwrap()).unwrap();
+            stream
+                .write_all(
+                    b"CONNECT api.example.com:443 HTTP/1.1\r\nHost: api.example.com:443\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).await.unwrap();
+                head.push(byte[0]);
+            }
+            assert!(head.starts_with(b"HTTP/1.1 200"));
+
+            // The client trusting the CA, and talking TLS through the tunnel
+            let mut client_config = ClientConfig::new();
+            client_config
+                .root_store
+                .add_pem_file(&mut &std::fs::read(directory.path().join("ca.pem")).unwrap()[..])
+                .unwrap();
+            let name = webpki::DNSNameRef::try_from_ascii_str("api.example.com").unwrap();
+            let stream = TlsConnector::from(Arc::new(client_config))
+                .connect(name, stream)
+                .await
+                .unwrap();
+
+            let (mut client, connection) = Builder::new()
+                .handshake::<_, HttpBody>(stream)
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+            let request = HttpRequest::get("/a")
+                .header("Host", "api.example.com")
+                .body(HttpBody::empty())
+                .unwrap();
+            let response = client.send_request(request).await.unwrap();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(body, "https://api.example.com");
+        });
+    }
+}
@@ -0,0 +1,580 @@
+//! Recording: forwarding requests to an upstream server and keeping the
+//! exchanges as HAR entries.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use futures_util::future::{self, Either};
+use hyper::{
+    body,
+    client::HttpConnector,
+    header::{HeaderMap, ACCEPT_ENCODING, HOST},
+    Body as HttpBody, Client, Request as HttpRequest, Response as HttpResponse, Uri, Version,
+};
+use hyper_rustls::HttpsConnector;
+use url::Url;
+
+use crate::errors::*;
+use crate::har::{
+    self, Compression, Content, Cookies, Creator, Entries, Har, HarError, Headers, Log, PostData,
+};
+
+/// How often to look for appended entries to save
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Appended entries get saved along the way once there are at least one for
+/// every `SAVE_GROWTH` saved ones, so rewriting the file as it grows takes
+/// time in proportion to its final size
+const SAVE_GROWTH: usize = 8;
+
+/// Headers about a single connection, which aren't forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The server requests are forwarded to, over HTTP or HTTPS
+pub struct Upstream {
+    client: Client<HttpsConnector<HttpConnector>>,
+    base: Url,
+}
+
+impl Upstream {
+    pub fn new(base: Url) -> Self {
+        Self {
+            client: Client::builder().build(HttpsConnector::new()),
+            base,
+        }
+    }
+
+    /// Forward `request` to the upstream server, returning its response along
+    /// with the exchange as a HAR entry
+    pub async fn forward(
+        &self,
+        request: HttpRequest<HttpBody>,
+    ) -> Result<(HttpResponse<HttpBody>, Entries), AppError> {
+        let started = Utc::now();
+        let start = Instant::now();
+
+        let (mut parts, request_body) = request.into_parts();
+        let request_body = body::to_bytes(request_body).await.context(Forwarding)?;
+
+        let recorded_request = self.prepare(&mut parts, &request_body)?;
+
+        let response = self
+            .client
+            .request(HttpRequest::from_parts(parts, request_body.into()))
+            .await
+            .context(Forwarding)?;
+        let wait = start.elapsed();
+
+        let (mut parts, response_body) = response.into_parts();
+        let response_body = body::to_bytes(response_body).await.context(Forwarding)?;
+        let receive = start.elapsed() - wait;
+
+        remove_hop_by_hop(&mut parts.headers);
+        let entry = Entries {
+            started_date_time: started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            time: millis(wait + receive),
+            request: recorded_request,
+            response: record_response(&parts, &response_body),
+            timings: har::Timings {
+                send: Some(0.0),
+                wait: Some(millis(wait)),
+                receive: Some(millis(receive)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        Ok((HttpResponse::from_parts(parts, response_body.into()), entry))
+    }
+
+    /// Point `parts` at the upstream server, returning the request as it's
+    /// to be recorded
+    fn prepare(
+        &self,
+        parts: &mut http::request::Parts,
+        body: &[u8],
+    ) -> Result<har::Request, AppError> {
+        let url = upstream_url(&self.base, &parts.uri);
+        parts.uri = url.as_str().parse::<Uri>().context(UpstreamUrl)?;
+        // The client fills the upstream's in
+        parts.headers.remove(HOST);
+        // Bodies are recorded as they come, so they'd better not be compressed
+        parts.headers.remove(ACCEPT_ENCODING);
+        remove_hop_by_hop(&mut parts.headers);
+        let recorded = record_request(&url, parts, body);
+        // Recorded with the version the client talked, but the client picks
+        // the one it talks to the upstream
+        parts.version = Version::default();
+        Ok(recorded)
+    }
+}
+
+/// Where requests missing from the HAR files go, when anywhere
+pub struct Passthrough {
+    pub upstream: Upstream,
+    /// Where to keep passed through exchanges, if at all
+    pub recording: Option<Arc<Recording>>,
+}
+
+/// A HAR file entries get appended to. They are kept in memory and saved in
+/// batches, replacing the whole file at once (see `har::to_path`), so that a
+/// crash midway leaves the previous version in place.
+///
+/// Along the way, the file is only rewritten once a batch is big enough next
+/// to what's saved already (see `SAVE_GROWTH`), so that a long recording
+/// doesn't get rewritten over and over: a crash loses that batch at most.
+/// Everything left gets saved on the way out.
+pub struct Recording {
+    path: PathBuf,
+    /// Told by the extension of the file
+    compression: Compression,
+    state: Mutex<State>,
+}
+
+struct State {
+    har: Har,
+    /// How many of the entries are saved
+    saved: usize,
+}
+
+impl State {
+    fn unsaved(&self) -> usize {
+        self.har.log.entries.len() - self.saved
+    }
+}
+
+impl Recording {
+    /// Append to the HAR at `path`, or to a new one if there's none yet
+    pub fn open(path: PathBuf) -> Result<Self, HarError> {
+        let compression = Compression::from_extension(&path);
+        compression.check_writable()?;
+
+        let har = if path.exists() {
+            har::from_path(&path)?
+        } else {
+            Har {
+                log: Log {
+                    version: Some("1.2".into()),
+                    creator: Creator {
+                        name: "harPlay".into(),
+                        version: env!("CARGO_PKG_VERSION").into(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            }
+        };
+        let saved = har.log.entries.len();
+
+        Ok(Self {
+            path,
+            compression,
+            state: Mutex::new(State { har, saved }),
+        })
+    }
+
+    /// Keep `entry`, to be saved with the next batch
+    pub fn append(&self, entry: Entries) -> Result<(), AppError> {
+        let mut state = self.state.lock().map_err(|_| AppError::DatabaseLock)?;
+        state.har.log.entries.push(entry);
+        Ok(())
+    }
+
+    /// Write the HAR out if entries were appended since it last was, blocking
+    /// on the file system
+    pub fn save(&self) -> Result<(), AppError> {
+        self.save_if(|state| state.unsaved() > 0)
+    }
+
+    /// Save only if enough entries were appended for it to be worth rewriting
+    /// the whole file
+    fn save_batch(&self) -> Result<(), AppError> {
+        self.save_if(|state| state.unsaved() > 0 && state.unsaved() * SAVE_GROWTH >= state.saved)
+    }
+
+    /// Appending waits while saving: the HAR is written straight out of
+    /// memory, rather than out of a copy of it
+    fn save_if(&self, due: impl Fn(&State) -> bool) -> Result<(), AppError> {
+        let mut state = self.state.lock().map_err(|_| AppError::DatabaseLock)?;
+        if !due(&state) {
+            return Ok(());
+        }
+
+        // Compressed files are for keeping, plain ones may be read as they are
+        let format = match self.compression {
+            Compression::None => har::Format::Pretty,
+            _ => har::Format::Compact,
+        };
+        har::to_path(&state.har, &self.path, format, self.compression).context(Saving)?;
+        state.saved = state.har.log.entries.len();
+        Ok(())
+    }
+
+    /// Save off the async runtime, logging what goes wrong
+    pub async fn flush(self: Arc<Self>) {
+        Self::save_off_runtime(move || self.save()).await
+    }
+
+    async fn save_off_runtime<F>(save: F)
+    where
+        F: FnOnce() -> Result<(), AppError> + Send + 'static,
+    {
+        match tokio::task::spawn_blocking(save).await {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => log::error!("{}", error),
+            Err(error) => log::error!("Error saving the recorded HAR: {}", error),
+        }
+    }
+
+    /// Run `serve` until it's done or the process is interrupted (Ctrl-C),
+    /// saving big enough batches of what was recorded every `SAVE_INTERVAL`,
+    /// and everything once more on the way out
+    pub async fn serve<F, E>(self: Arc<Self>, serve: F) -> Result<(), E>
+    where
+        F: Future<Output = Result<(), E>>,
+    {
+        let recording = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::delay_for(SAVE_INTERVAL).await;
+                let recording = recording.clone();
+                Self::save_off_runtime(move || recording.save_batch()).await;
+            }
+        });
+
+        let result = match future::select(Box::pin(serve), Box::pin(tokio::signal::ctrl_c())).await
+        {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => {
+                log::info!("Interrupted, saving the recording");
+                Ok(())
+            }
+        };
+        self.flush().await;
+        result
+    }
+}
+
+/// Where `uri` (path and query) is on the upstream server, under the path of `base`
+fn upstream_url(base: &Url, uri: &Uri) -> Url {
+    let mut url = base.clone();
+    url.set_path(&format!(
+        "{}{}",
+        base.path().trim_end_matches('/'),
+        uri.path()
+    ));
+    url.set_query(uri.query());
+    url
+}
+
+/// HTTP/2 has no place for these either
+pub fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+fn record_request(url: &Url, parts: &http::request::Parts, body: &[u8]) -> har::Request {
+    let cookies = parts
+        .headers
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .flat_map(|value| {
+            String::from_utf8_lossy(value.as_bytes())
+                .split(';')
+                .filter_map(|pair| cookie(pair.trim()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let post_data = if body.is_empty() {
+        None
+    } else {
+        let (text, encoding) = text(body);
+        Some(PostData {
+            mime_type: content_type(&parts.headers),
+            text: Some(text),
+            encoding,
+            ..Default::default()
+        })
+    };
+
+    har::Request {
+        method: parts.method.to_string(),
+        url: url.to_string(),
+        http_version: format!("{:?}", parts.version),
+        cookies,
+        headers: headers(&parts.headers),
+        query_string: url
+            .query_pairs()
+            .map(|(name, value)| har::QueryString {
+                name: name.into_owned(),
+                value: value.into_owned(),
+                ..Default::default()
+            })
+            .collect(),
+        post_data,
+        headers_size: -1,
+        body_size: body.len() as i64,
+        ..Default::default()
+    }
+}
+
+fn record_response(parts: &http::response::Parts, body: &[u8]) -> har::Response {
+    let (text, encoding) = text(body);
+
+    har::Response {
+        status: parts.status.as_u16().into(),
+        status_text: parts.status.canonical_reason().unwrap_or_default().into(),
+        http_version: format!("{:?}", parts.version),
+        cookies: parts
+            .headers
+            .get_all(hyper::header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| set_cookie(&String::from_utf8_lossy(value.as_bytes())))
+            .collect(),
+        headers: headers(&parts.headers),
+        content: Content {
+            size: Some(body.len() as f64),
+            mime_type: Some(content_type(&parts.headers)),
+            text: Some(text).filter(|text| !text.is_empty()),
+            encoding,
+            ..Default::default()
+        },
+        redirect_url: parts
+            .headers
+            .get(hyper::header::LOCATION)
+            .map(|location| String::from_utf8_lossy(location.as_bytes()).into_owned())
+            .unwrap_or_default(),
+        headers_size: -1,
+        body_size: body.len() as i64,
+        ..Default::default()
+    }
+}
+
+fn headers(headers: &HeaderMap) -> Vec<Headers> {
+    headers
+        .iter()
+        .map(|(name, value)| Headers {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn content_type(headers: &HeaderMap) -> String {
+    headers
+        .get(hyper::header::CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .unwrap_or_default()
+}
+
+/// Bodies are kept as text when they are, and base64 encoded otherwise
+fn text(body: &[u8]) -> (String, Option<String>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.into(), None),
+        Err(_) => (base64::encode(body), Some("base64".into())),
+    }
+}
+
+fn cookie(pair: &str) -> Option<Cookies> {
+    let mut pair = pair.splitn(2, '=');
+    let name = pair.next().filter(|name| !name.is_empty())?;
+    Some(Cookies {
+        name: name.into(),
+        value: pair.next().unwrap_or_default().into(),
+        ..Default::default()
+    })
+}
+
+fn set_cookie(header: &str) -> Option<Cookies> {
+    let mut attributes = header.split(';').map(str::trim);
+    let mut cookie = cookie(attributes.next()?)?;
+
+    for attribute in attributes {
+        let mut attribute = attribute.splitn(2, '=');
+        let name = attribute.next().unwrap_or_default().to_ascii_lowercase();
+        let value = attribute.next().map(String::from);
+        match name.as_str() {
+            "path" => cookie.path = value,
+            "domain" => cookie.domain = value,
+            "expires" => cookie.expires = value,
+            "httponly" => cookie.http_only = Some(true),
+            "secure" => cookie.secure = Some(true),
+            _ => {}
+        }
+    }
+
+    Some(cookie)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("http://upstream:8080", "/a/b?c=d", "http://upstream:8080/a/b?c=d"; "root")]
+    #[test_case("https://upstream/api/", "/a", "https://upstream/api/a"; "base path")]
+    #[test_case("https://upstream/api?x=y", "/a", "https://upstream/api/a"; "base query")]
+    fn it_maps_urls_upstream(base: &str, uri: &str, expected: &str) {
+        let url = upstream_url(&base.parse().unwrap(), &uri.parse().unwrap());
+        assert_eq!(url.as_str(), expected);
+    }
+
+    #[test]
+    fn it_records_exchanges() {
+        let url: Url = "https://upstream/search?q=har".parse().unwrap();
+        let (parts, _) = HttpRequest::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .header("Cookie", "session=1; theme=dark")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let request = record_request(&url, &parts, b"{}");
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.url, "https://upstream/search?q=har");
+        assert_eq!(request.http_version, "HTTP/1.1");
+        assert_eq!(request.query_string[0].value, "har");
+        assert_eq!(request.cookies.len(), 2);
+        assert_eq!(request.cookies[1].value, "dark");
+        let post_data = request.post_data.unwrap();
+        assert_eq!(post_data.mime_type, "application/json");
+        assert_eq!(post_data.text.as_deref(), Some("{}"));
+
+        let (parts, _) = HttpResponse::builder()
+            .status(302)
+            .header("Location", "/elsewhere")
+            .header("Set-Cookie", "session=2; Path=/; HttpOnly")
+            .header("Connection", "close")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let response = record_response(&parts, &[0, 1, 2, 255]);
+
+        assert_eq!(response.status, 302);
+        assert_eq!(response.status_text, "Found");
+        assert_eq!(response.redirect_url, "/elsewhere");
+        assert_eq!(response.cookies[0].path.as_deref(), Some("/"));
+        assert_eq!(response.cookies[0].http_only, Some(true));
+        assert_eq!(response.content.text.as_deref(), Some("AAEC/w=="));
+        assert_eq!(response.content.encoding.as_deref(), Some("base64"));
+        assert_eq!(response.body_size, 4);
+    }
+
+    #[test]
+    fn it_records_what_the_client_sent() {
+        let upstream = Upstream::new("http://upstream:8080".parse().unwrap());
+        let (mut parts, _) = HttpRequest::get("http://harplay/a")
+            .version(Version::HTTP_2)
+            .header(HOST, "harplay")
+            .header(ACCEPT_ENCODING, "gzip")
+            .header("Connection", "close")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let request = upstream.prepare(&mut parts, b"").unwrap();
+
+        assert_eq!(request.url, "http://upstream:8080/a");
+        assert_eq!(request.http_version, "HTTP/2.0");
+        assert!(request.headers.is_empty());
+        assert_eq!(parts.uri, "http://upstream:8080/a");
+        assert_eq!(parts.version, Version::HTTP_11);
+        assert!(parts.headers.is_empty());
+    }
+
+    #[test]
+    fn it_appends_to_har_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recorded.har");
+
+        let recording = Recording::open(path.clone()).unwrap();
+        recording.append(Entries::default()).unwrap();
+        recording.save().unwrap();
+        drop(recording);
+
+        let recording = Recording::open(path.clone()).unwrap();
+        recording.append(Entries::default()).unwrap();
+        // Nothing is written until saved
+        assert_eq!(har::from_path(&path).unwrap().log.entries.len(), 1);
+        recording.save().unwrap();
+
+        let har = har::from_path(&path).unwrap();
+        assert_eq!(har.log.creator.name, "harPlay");
+        assert_eq!(har.log.entries.len(), 2);
+    }
+
+    #[test]
+    fn it_saves_growing_batches() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("recorded.har");
+        let saved = || har::from_path(&path).unwrap().log.entries.len();
+
+        let recording = Recording::open(path.clone()).unwrap();
+        for _ in 0..16 {
+            recording.append(Entries::default()).unwrap();
+        }
+        recording.save_batch().unwrap();
+        assert_eq!(saved(), 16);
+
+        recording.append(Entries::default()).unwrap();
+        recording.save_batch().unwrap();
+        assert_eq!(saved(), 16);
+
+        recording.append(Entries::default()).unwrap();
+        recording.save_batch().unwrap();
+        assert_eq!(saved(), 18);
+    }
+
+    #[test_case("recorded.har.gz", Compression::Gzip; "gzip")]
+    #[test_case("recorded.har.zst", Compression::Zstd; "zstd")]
+    fn it_keeps_har_files_compressed(name: &str, compression: Compression) {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join(name);
+        let compressed = || Compression::detect(&std::fs::read(&path).unwrap());
+
+        for count in 1..=2 {
+            let recording = Recording::open(path.clone()).unwrap();
+            recording.append(Entries::default()).unwrap();
+            recording.save().unwrap();
+            assert_eq!(compressed(), compression);
+            let mut json = Vec::new();
+            har::open(&path, None)
+                .unwrap()
+                .read_to_end(&mut json)
+                .unwrap();
+            assert!(!json.contains(&b'\n'));
+            assert_eq!(
+                har::from_reader(&json[..]).unwrap().log.entries.len(),
+                count
+            );
+        }
+    }
+
+    #[test]
+    fn it_refuses_to_record_into_zips() {
+        let directory = tempfile::tempdir().unwrap();
+        assert!(Recording::open(directory.path().join("recorded.zip")).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::har;
+use crate::req_resp::Response;
+
+/// Loaded requests (as `METHOD URL`), each with a digest of the responses
+/// recorded for it, to tell reloads apart
+pub type Requests = BTreeMap<String, u64>;
+
+/// Keep `response` as one more of those recorded for `request`
+pub fn add_request(requests: &mut Requests, request: String, response: &Response) {
+    let mut hasher = DefaultHasher::new();
+    requests.get(&request).hash(&mut hasher);
+    response.status_code.hash(&mut hasher);
+    for header in response.headers.iter() {
+        header.name.hash(&mut hasher);
+        header.value.hash(&mut hasher);
+    }
+    response.body.hash(&mut hasher);
+    requests.insert(request, hasher.finish());
+}
+
+/// Modification times of the HAR files found in the watched paths
+#[derive(Debug, PartialEq)]
+pub struct Snapshot(Vec<(PathBuf, Option<SystemTime>)>);
+
+impl Snapshot {
+    pub fn take(paths: &[PathBuf]) -> Self {
+        let files = har::find_files(paths).unwrap_or_else(|error| {
+            log::debug!("Error looking for HAR files to watch: {}", error);
+            Vec::new()
+        });
+
+        Self(
+            files
+                .into_iter()
+                .map(|path| {
+                    let modified = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok();
+                    (path, modified)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Call `reload` from a background thread whenever HAR files in `paths` change,
+/// appear or go away, checking every `interval`
+pub fn watch<F: FnMut() + Send + 'static>(paths: Vec<PathBuf>, interval: Duration, mut reload: F) {
+    // Changes from now on count, even those before the thread gets going
+    let mut snapshot = Snapshot::take(&paths);
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+
+        let current = Snapshot::take(&paths);
+        if current != snapshot {
+            snapshot = current;
+            reload();
+        }
+    });
+}
+
+/// Requests a reload added, removed, or changed the responses of
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff<'a> {
+    pub added: Vec<&'a str>,
+    pub removed: Vec<&'a str>,
+    pub changed: Vec<&'a str>,
+}
+
+impl<'a> Diff<'a> {
+    pub fn new(old: &'a Requests, new: &'a Requests) -> Self {
+        let mut diff = Self::default();
+        for (request, digest) in new.iter() {
+            match old.get(request) {
+                None => diff.added.push(request),
+                Some(old_digest) if old_digest != digest => diff.changed.push(request),
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .keys()
+            .filter(|request| !new.contains_key(*request))
+            .map(String::as_str)
+            .collect();
+        diff
+    }
+}
+
+/// Log which requests a reload added, removed and changed
+pub fn log_diff(old: &Requests, new: &Requests) {
+    let diff = Diff::new(old, new);
+
+    for request in diff.added.iter() {
+        log::info!("+ {}", request);
+    }
+    for request in diff.removed.iter() {
+        log::info!("- {}", request);
+    }
+    for request in diff.changed.iter() {
+        log::info!("~ {}", request);
+    }
+
+    log::info!(
+        "Reloaded {} requests: {} added, {} removed, {} changed",
+        new.len(),
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::req_resp::Timings;
+
+    /// Move the modification time of `path` a minute ahead
+    fn touch(path: &std::path::Path) {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[test]
+    fn snapshots_follow_har_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let paths = vec![directory.path().to_path_buf()];
+        let first = directory.path().join("first.har");
+        fs::write(&first, "{}").unwrap();
+
+        let snapshot = Snapshot::take(&paths);
+        assert_eq!(snapshot.0.len(), 1);
+        assert_eq!(Snapshot::take(&paths), snapshot);
+
+        fs::write(directory.path().join("notes.txt"), "").unwrap();
+        assert_eq!(Snapshot::take(&paths), snapshot);
+
+        fs::write(directory.path().join("second.har"), "{}").unwrap();
+        let snapshot = Snapshot::take(&paths);
+        assert_eq!(snapshot.0.len(), 2);
+
+        // Timestamps are too coarse on some filesystems to tell writes apart
+        touch(&first);
+        assert_ne!(Snapshot::take(&paths), snapshot);
+
+        fs::remove_file(&first).unwrap();
+        assert_eq!(Snapshot::take(&paths).0.len(), 1);
+    }
+
+    fn response(body: &str) -> Response {
+        Response {
+            status_code: 200,
+            headers: Vec::new(),
+            body: Some(body.into()),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diffs_tell_changed_responses() {
+        let mut old = Requests::new();
+        add_request(&mut old, "GET /kept".into(), &response("kept"));
+        add_request(&mut old, "GET /edited".into(), &response("before"));
+        add_request(&mut old, "GET /more".into(), &response("first"));
+        add_request(&mut old, "GET /gone".into(), &response("gone"));
+
+        let mut new = Requests::new();
+        add_request(&mut new, "GET /kept".into(), &response("kept"));
+        add_request(&mut new, "GET /edited".into(), &response("after"));
+        add_request(&mut new, "GET /more".into(), &response("first"));
+        add_request(&mut new, "GET /more".into(), &response("second"));
+        add_request(&mut new, "GET /new".into(), &response("new"));
+
+        assert_eq!(
+            Diff::new(&old, &new),
+            Diff {
+                added: vec!["GET /new"],
+                removed: vec!["GET /gone"],
+                changed: vec!["GET /edited", "GET /more"],
+            }
+        );
+    }
+
+    #[test]
+    fn watching_reloads_on_changes() {
+        let directory = tempfile::tempdir().unwrap();
+        let har = directory.path().join("first.har");
+        fs::write(&har, "{}").unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        watch(
+            vec![directory.path().to_path_buf()],
+            Duration::from_millis(10),
+            move || {
+                let _ = sender.send(());
+            },
+        );
+
+        touch(&har);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
@@ -73,6 +73,36 @@ impl FromStr for ResponderBehaviour {
     }
 }
 
+/// What to do with requests already loaded from a previous source (HAR file)
+#[derive(Debug, Clone, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Add the responses to the sequence
+    Append,
+    /// Keep the responses of the first source
+    FirstWins,
+    /// Replace the responses with the ones of the last source
+    LastWins,
+}
+
+impl DuplicatePolicy {
+    pub fn variants() -> &'static [&'static str] {
+        &["append", "first-wins", "last-wins"]
+    }
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "append" => Ok(Self::Append),
+            "first-wins" => Ok(Self::FirstWins),
+            "last-wins" => Ok(Self::LastWins),
+            _ => Err("Unrecognized duplicate policy"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use test_case::test_case;
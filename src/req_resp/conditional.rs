@@ -0,0 +1,261 @@
+use chrono::{DateTime, FixedOffset};
+
+use super::errors::*;
+use super::{HarResponder, Request, Respond, Response};
+
+/// Headers worth keeping on a `304 Not Modified` (RFC 7232, section 4.1)
+const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-location",
+    "date",
+    "etag",
+    "expires",
+    "last-modified",
+    "vary",
+];
+
+/// Answers conditional requests (`If-None-Match`, `If-Modified-Since`) with
+/// `304 Not Modified` when the recorded validators match.
+#[derive(Debug)]
+pub struct ConditionalResponder<R> {
+    inner: R,
+}
+
+impl<R: HarResponder> ConditionalResponder<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    fn respond_with(
+        &mut self,
+        request: &Request,
+        respond: Respond<R>,
+    ) -> Result<Response, ResponderError> {
+        let response = respond(&mut self.inner, request)?;
+
+        let is_safe_method = request.method.eq_ignore_ascii_case("GET")
+            || request.method.eq_ignore_ascii_case("HEAD");
+
+        if is_safe_method && response.status_code == 200 && is_not_modified(request, &response) {
+            Ok(not_modified(response))
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+impl<R: HarResponder> HarResponder for ConditionalResponder<R> {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to_other)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::peek)
+    }
+
+    fn record(&mut self, request: Request, response: Response) {
+        self.inner.record(request, response)
+    }
+}
+
+/// Use the cache entry's `eTag` as validator when the response itself has none
+pub fn fill_e_tag_from_cache(response: &mut Response, cache: &crate::har::Cache) {
+    if response.header("etag").is_some() {
+        return;
+    }
+
+    let e_tag = cache
+        .after_request
+        .iter()
+        .chain(cache.before_request.iter())
+        .map(|entity| entity.e_tag.trim())
+        .find(|e_tag| !e_tag.is_empty());
+
+    if let Some(e_tag) = e_tag {
+        response.set_header("ETag", e_tag);
+    }
+}
+
+fn is_not_modified(request: &Request, response: &Response) -> bool {
+    // `If-None-Match` takes precedence, `If-Modified-Since` is ignored when both are present
+    if let Some(if_none_match) = request.header("if-none-match") {
+        return response
+            .header("etag")
+            .is_some_and(|e_tag| e_tag_matches(if_none_match, e_tag));
+    }
+
+    match (
+        request
+            .header("if-modified-since")
+            .and_then(parse_http_date),
+        response.header("last-modified").and_then(parse_http_date),
+    ) {
+        (Some(since), Some(last_modified)) => last_modified <= since,
+        _ => false,
+    }
+}
+
+/// Weak comparison of an `If-None-Match` list against an entity tag
+fn e_tag_matches(if_none_match: &str, e_tag: &str) -> bool {
+    let e_tag = strip_weak(e_tag.trim());
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || strip_weak(candidate) == e_tag)
+}
+
+fn strip_weak(e_tag: &str) -> &str {
+    e_tag.strip_prefix("W/").unwrap_or(e_tag)
+}
+
+fn parse_http_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(date.trim()).ok()
+}
+
+fn not_modified(response: Response) -> Response {
+    Response {
+        status_code: 304,
+        headers: response
+            .headers
+            .into_iter()
+            .filter(|header| {
+                NOT_MODIFIED_HEADERS
+                    .iter()
+                    .any(|name| header.name.eq_ignore_ascii_case(name))
+            })
+            .collect(),
+        body: None,
+        timings: response.timings,
+        web_socket_messages: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+    use url::Url;
+
+    use super::*;
+    use crate::har::{Cache, CacheEntity};
+    use crate::req_resp::{Header, InMemoryResponder, ResponderBehaviour, Timings};
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: method.into(),
+            url: Url::parse("http://harplay/sw.js").unwrap(),
+            original_url: "http://harplay/sw.js".into(),
+            origin: None,
+            headers: headers
+                .iter()
+                .map(|(name, value)| Header {
+                    name: (*name).into(),
+                    value: (*value).into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn responder() -> ConditionalResponder<InMemoryResponder> {
+        let recorded = Response {
+            status_code: 200,
+            headers: vec![
+                Header {
+                    name: "Content-Type".into(),
+                    value: "application/javascript".into(),
+                },
+                Header {
+                    name: "ETag".into(),
+                    value: "W/\"v1\"".into(),
+                },
+                Header {
+                    name: "Last-Modified".into(),
+                    value: "Wed, 21 Oct 2015 07:28:00 GMT".into(),
+                },
+            ],
+            body: Some("self.skipWaiting()".into()),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        };
+        ConditionalResponder::new(InMemoryResponder::new(
+            ResponderBehaviour::AlwaysFirst,
+            vec![(request("GET", &[]), recorded)].into_iter(),
+        ))
+    }
+
+    #[test_case(&[], 200)]
+    #[test_case(&[("If-None-Match", "\"v1\"")], 304)]
+    #[test_case(&[("If-None-Match", "\"v0\", W/\"v1\"")], 304)]
+    #[test_case(&[("If-None-Match", "*")], 304)]
+    #[test_case(&[("If-None-Match", "\"v2\"")], 200)]
+    #[test_case(&[("If-Modified-Since", "Wed, 21 Oct 2015 07:28:00 GMT")], 304)]
+    #[test_case(&[("If-Modified-Since", "Thu, 22 Oct 2015 07:28:00 GMT")], 304)]
+    #[test_case(&[("If-Modified-Since", "Tue, 20 Oct 2015 07:28:00 GMT")], 200)]
+    #[test_case(&[("If-Modified-Since", "yesterday")], 200)]
+    #[test_case(&[("If-None-Match", "\"v2\""), ("If-Modified-Since", "Thu, 22 Oct 2015 07:28:00 GMT")], 200)]
+    fn conditional_requests(headers: &[(&str, &str)], status_code: u16) {
+        let response = responder().respond_to(&request("GET", headers)).unwrap();
+
+        assert_eq!(response.status_code, status_code);
+        if status_code == 304 {
+            assert_eq!(response.body, None);
+            assert_eq!(response.header("etag"), Some("W/\"v1\""));
+            assert_eq!(response.header("content-type"), None);
+        }
+    }
+
+    #[test]
+    fn unsafe_methods_untouched() {
+        let mut responder = ConditionalResponder::new(InMemoryResponder::new(
+            ResponderBehaviour::AlwaysFirst,
+            vec![(
+                request("POST", &[]),
+                Response {
+                    status_code: 200,
+                    headers: vec![Header {
+                        name: "ETag".into(),
+                        value: "\"v1\"".into(),
+                    }],
+                    body: None,
+                    timings: Timings::default(),
+                    web_socket_messages: Vec::new(),
+                },
+            )]
+            .into_iter(),
+        ));
+
+        let response = responder
+            .respond_to(&request("POST", &[("If-None-Match", "\"v1\"")]))
+            .unwrap();
+        assert_eq!(response.status_code, 200);
+    }
+
+    #[test]
+    fn e_tag_from_cache() {
+        let cache = Cache {
+            before_request: None,
+            after_request: Some(CacheEntity {
+                e_tag: "\"cached\"".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut response = Response {
+            status_code: 200,
+            headers: Vec::new(),
+            body: None,
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        };
+        fill_e_tag_from_cache(&mut response, &cache);
+        assert_eq!(response.header("etag"), Some("\"cached\""));
+
+        response.set_header("ETag", "\"own\"");
+        fill_e_tag_from_cache(&mut response, &cache);
+        assert_eq!(response.header("etag"), Some("\"own\""));
+    }
+}
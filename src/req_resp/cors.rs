@@ -0,0 +1,334 @@
+use super::errors::*;
+use super::{HarResponder, Request, Respond, Response, Timings};
+
+/// What the CORS layer allows
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CorsConfig {
+    /// Allowed origins; any origin is allowed when empty
+    pub allowed_origins: Vec<String>,
+    /// Allowed methods; the requested method is allowed when empty
+    pub allowed_methods: Vec<String>,
+    /// Allowed request headers; the requested headers are allowed when empty
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// Seconds a preflight answer can be cached for
+    pub max_age: Option<u64>,
+}
+
+impl CorsConfig {
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Value for `Access-Control-Allow-Origin`; browsers reject the `*`
+    /// wildcard on credentialed requests, so echo the origin back then.
+    fn allow_origin_value(&self, origin: &str) -> String {
+        if self.allowed_origins.is_empty() && !self.allow_credentials {
+            "*".into()
+        } else {
+            origin.into()
+        }
+    }
+}
+
+/// Answers CORS preflights itself and injects `Access-Control-Allow-*`
+/// headers into the responses of the wrapped responder.
+#[derive(Debug)]
+pub struct CorsResponder<R> {
+    inner: R,
+    config: CorsConfig,
+}
+
+impl<R: HarResponder> CorsResponder<R> {
+    pub fn new(inner: R, config: CorsConfig) -> Self {
+        Self { inner, config }
+    }
+
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let mut response = Response {
+            status_code: 204,
+            headers: Vec::new(),
+            body: None,
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        };
+        self.add_headers(&mut response, origin);
+
+        response.set_header(
+            "access-control-allow-methods",
+            if self.config.allowed_methods.is_empty() {
+                method.into()
+            } else {
+                self.config.allowed_methods.join(", ")
+            },
+        );
+
+        let allowed_headers = if self.config.allowed_headers.is_empty() {
+            request
+                .header("access-control-request-headers")
+                .map(Into::into)
+        } else {
+            Some(self.config.allowed_headers.join(", "))
+        };
+        if let Some(allowed_headers) = allowed_headers {
+            response.set_header("access-control-allow-headers", allowed_headers);
+        }
+
+        if let Some(max_age) = self.config.max_age {
+            response.set_header("access-control-max-age", max_age.to_string());
+        }
+
+        response
+    }
+
+    fn add_headers(&self, response: &mut Response, origin: &str) {
+        response.headers.retain(|header| {
+            !header
+                .name
+                .to_ascii_lowercase()
+                .starts_with("access-control-")
+        });
+
+        response.set_header(
+            "access-control-allow-origin",
+            self.config.allow_origin_value(origin),
+        );
+        if self.config.allow_credentials {
+            response.set_header("access-control-allow-credentials", "true");
+        }
+        if !self.config.allowed_origins.is_empty() || self.config.allow_credentials {
+            vary_on_origin(response);
+        }
+    }
+
+    fn respond_with(
+        &mut self,
+        request: &Request,
+        respond: Respond<R>,
+    ) -> Result<Response, ResponderError> {
+        let origin = match request.header("origin") {
+            Some(origin) if self.config.allows_origin(origin) => origin,
+            _ => return respond(&mut self.inner, request),
+        };
+
+        if request.method.eq_ignore_ascii_case("OPTIONS") {
+            if let Some(method) = request.header("access-control-request-method") {
+                return Ok(self.preflight(request, origin, method));
+            }
+        }
+
+        let mut response = respond(&mut self.inner, request)?;
+        self.add_headers(&mut response, origin);
+        Ok(response)
+    }
+}
+
+/// Add `Origin` to the headers the response varies on, keeping the recorded
+/// ones (`Accept-Encoding`, `Cookie`...)
+fn vary_on_origin(response: &mut Response) {
+    let varies: Vec<&str> = response
+        .headers
+        .iter()
+        .filter(|header| header.name.eq_ignore_ascii_case("vary"))
+        .flat_map(|header| header.value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    if varies
+        .iter()
+        .any(|name| *name == "*" || name.eq_ignore_ascii_case("origin"))
+    {
+        return;
+    }
+
+    let vary = varies
+        .into_iter()
+        .chain(std::iter::once("Origin"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    response.set_header("vary", vary);
+}
+
+impl<R: HarResponder> HarResponder for CorsResponder<R> {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to_other)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::peek)
+    }
+
+    fn record(&mut self, request: Request, response: Response) {
+        self.inner.record(request, response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+    use url::Url;
+
+    use super::*;
+    use crate::req_resp::{Header, InMemoryResponder, ResponderBehaviour};
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: method.into(),
+            url: Url::parse("http://harplay/api/").unwrap(),
+            original_url: "http://harplay/api/".into(),
+            origin: None,
+            headers: headers
+                .iter()
+                .map(|(name, value)| Header {
+                    name: (*name).into(),
+                    value: (*value).into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn responder(config: CorsConfig) -> CorsResponder<InMemoryResponder> {
+        responder_with(config, &[])
+    }
+
+    fn responder_with(
+        config: CorsConfig,
+        headers: &[(&str, &str)],
+    ) -> CorsResponder<InMemoryResponder> {
+        let recorded = Response {
+            status_code: 200,
+            headers: std::iter::once(("Access-Control-Allow-Origin", "https://app.example.com"))
+                .chain(headers.iter().cloned())
+                .map(|(name, value)| Header {
+                    name: name.into(),
+                    value: value.into(),
+                })
+                .collect(),
+            body: Some("{}".into()),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        };
+        CorsResponder::new(
+            InMemoryResponder::new(
+                ResponderBehaviour::AlwaysFirst,
+                vec![(request("GET", &[]), recorded)].into_iter(),
+            ),
+            config,
+        )
+    }
+
+    #[test]
+    fn answers_preflights() {
+        let mut responder = responder(CorsConfig {
+            max_age: Some(600),
+            ..Default::default()
+        });
+
+        let response = responder
+            .respond_to(&request(
+                "OPTIONS",
+                &[
+                    ("origin", "http://localhost:8080"),
+                    ("access-control-request-method", "POST"),
+                    ("access-control-request-headers", "content-type"),
+                ],
+            ))
+            .unwrap();
+
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.header("access-control-allow-origin"), Some("*"));
+        assert_eq!(
+            response.header("access-control-allow-methods"),
+            Some("POST")
+        );
+        assert_eq!(
+            response.header("access-control-allow-headers"),
+            Some("content-type")
+        );
+        assert_eq!(response.header("access-control-max-age"), Some("600"));
+    }
+
+    #[test]
+    fn injects_headers_into_replayed_responses() {
+        let mut responder = responder(CorsConfig {
+            allowed_origins: vec!["http://localhost:8080".into()],
+            allow_credentials: true,
+            ..Default::default()
+        });
+
+        let response = responder
+            .respond_to(&request("GET", &[("origin", "http://localhost:8080")]))
+            .unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some("http://localhost:8080")
+        );
+        assert_eq!(
+            response.header("access-control-allow-credentials"),
+            Some("true")
+        );
+        assert_eq!(response.header("vary"), Some("Origin"));
+    }
+
+    #[test_case(&[("Vary", "Accept-Encoding")], "Accept-Encoding, Origin"; "after recorded ones")]
+    #[test_case(&[("Vary", "Accept-Encoding"), ("vary", "Cookie")], "Accept-Encoding, Cookie, Origin"; "merging several")]
+    #[test_case(&[("Vary", "origin, Cookie")], "origin, Cookie"; "already there")]
+    #[test_case(&[("Vary", "*")], "*"; "on everything")]
+    fn keeps_recorded_vary_headers(headers: &[(&str, &str)], expected: &str) {
+        let mut responder = responder_with(
+            CorsConfig {
+                allowed_origins: vec!["http://localhost:8080".into()],
+                ..Default::default()
+            },
+            headers,
+        );
+
+        let response = responder
+            .respond_to(&request("GET", &[("origin", "http://localhost:8080")]))
+            .unwrap();
+
+        let varies: Vec<_> = response
+            .headers
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case("vary"))
+            .map(|header| header.value.as_str())
+            .collect();
+        assert_eq!(varies.join(", "), expected);
+    }
+
+    #[test]
+    fn ignores_disallowed_origins() {
+        let mut responder = responder(CorsConfig {
+            allowed_origins: vec!["http://localhost:8080".into()],
+            ..Default::default()
+        });
+
+        let response = responder
+            .respond_to(&request("GET", &[("origin", "http://evil.example.com")]))
+            .unwrap();
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some("https://app.example.com")
+        );
+
+        assert!(responder
+            .respond_to(&request(
+                "OPTIONS",
+                &[
+                    ("origin", "http://evil.example.com"),
+                    ("access-control-request-method", "POST"),
+                ],
+            ))
+            .is_err());
+    }
+}
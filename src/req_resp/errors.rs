@@ -28,6 +28,7 @@ impl std::error::Error for IntoRequestError {}
 pub enum ResponderError {
     RequestNotFound,
     ResponseNotFound,
+    ReadingBody,
 }
 
 impl std::error::Error for ResponderError {}
@@ -40,6 +41,7 @@ impl std::fmt::Display for ResponderError {
             match self {
                 Self::RequestNotFound => "Request not found",
                 Self::ResponseNotFound => "Response not found",
+                Self::ReadingBody => "Error reading stored response body",
             }
         )
     }
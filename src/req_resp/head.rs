@@ -0,0 +1,194 @@
+use super::errors::*;
+use super::{HarResponder, Request, Respond, Response};
+
+/// Answers `HEAD` requests without a recorded `HEAD` entry out of the
+/// matching `GET` entry: same status and headers, no body. The `GET` entry is
+/// only peeked at, so the `GET` sequence stays where it was.
+#[derive(Debug)]
+pub struct HeadResponder<R> {
+    inner: R,
+}
+
+impl<R: HarResponder> HeadResponder<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    fn respond_with(
+        &mut self,
+        request: &Request,
+        respond: Respond<R>,
+        respond_to_get: Respond<R>,
+    ) -> Result<Response, ResponderError> {
+        if !request.method.eq_ignore_ascii_case("HEAD") {
+            return respond(&mut self.inner, request);
+        }
+
+        match respond(&mut self.inner, request) {
+            Err(ResponderError::RequestNotFound) => {}
+            result => return result,
+        }
+
+        let mut response = respond_to_get(
+            &mut self.inner,
+            &Request {
+                method: "GET".into(),
+                ..request.clone()
+            },
+        )?;
+
+        if let Some(body) = response.body.take() {
+            response.set_header("content-length", body.len().to_string());
+        } else if response.header("content-length").is_none() {
+            response.set_header("content-length", "0");
+        }
+
+        Ok(response)
+    }
+}
+
+impl<R: HarResponder> HarResponder for HeadResponder<R> {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to, R::peek)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to_other, R::respond_to_other)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::peek, R::peek)
+    }
+
+    fn record(&mut self, request: Request, response: Response) {
+        self.inner.record(request, response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+    use crate::req_resp::{Header, InMemoryResponder, ResponderBehaviour, Timings};
+
+    fn request(method: &str, path: &str) -> Request {
+        let url = format!("http://harplay{}", path);
+        Request {
+            method: method.into(),
+            url: Url::parse(&url).unwrap(),
+            original_url: url,
+            origin: None,
+            headers: Vec::new(),
+        }
+    }
+
+    fn response(status_code: u16, body: Option<&str>) -> Response {
+        Response {
+            status_code,
+            headers: vec![Header {
+                name: "Content-Type".into(),
+                value: "text/plain".into(),
+            }],
+            body: body.map(Into::into),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        }
+    }
+
+    fn responder() -> HeadResponder<InMemoryResponder> {
+        HeadResponder::new(InMemoryResponder::new(
+            ResponderBehaviour::AlwaysFirst,
+            vec![
+                (request("GET", "/file.txt"), response(200, Some("hello"))),
+                (request("GET", "/head.txt"), response(200, Some("hello"))),
+                (request("HEAD", "/head.txt"), response(204, None)),
+            ]
+            .into_iter(),
+        ))
+    }
+
+    #[test]
+    fn synthesizes_head_from_get() {
+        let response = responder()
+            .respond_to(&request("HEAD", "/file.txt"))
+            .unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, None);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.header("content-length"), Some("5"));
+    }
+
+    #[test]
+    fn prefers_recorded_head() {
+        let response = responder()
+            .respond_to(&request("HEAD", "/head.txt"))
+            .unwrap();
+
+        assert_eq!(response.status_code, 204);
+    }
+
+    #[test]
+    fn keeps_get_sequences() {
+        let mut responder = HeadResponder::new(InMemoryResponder::new(
+            ResponderBehaviour::SequentialOnce,
+            vec![
+                (request("GET", "/file.txt"), response(200, Some("first"))),
+                (request("GET", "/file.txt"), response(200, Some("second"))),
+            ]
+            .into_iter(),
+        ));
+
+        for content_length in &["5", "5"] {
+            let response = responder.respond_to(&request("HEAD", "/file.txt")).unwrap();
+            assert_eq!(response.header("content-length"), Some(*content_length));
+        }
+        assert_eq!(
+            responder
+                .respond_to(&request("GET", "/file.txt"))
+                .unwrap()
+                .body,
+            Some("first".into())
+        );
+        assert_eq!(
+            responder
+                .respond_to(&request("HEAD", "/file.txt"))
+                .unwrap()
+                .header("content-length"),
+            Some("6")
+        );
+    }
+
+    #[test]
+    fn answers_recorded_head_after_synthesizing() {
+        let mut responder = responder();
+        responder.respond_to(&request("HEAD", "/file.txt")).unwrap();
+
+        responder.record(request("HEAD", "/file.txt"), response(204, None));
+        assert_eq!(
+            responder
+                .respond_to(&request("HEAD", "/file.txt"))
+                .unwrap()
+                .status_code,
+            204
+        );
+    }
+
+    #[test]
+    fn other_methods_untouched() {
+        let mut responder = responder();
+
+        assert_eq!(
+            responder
+                .respond_to(&request("GET", "/file.txt"))
+                .unwrap()
+                .body,
+            Some("hello".into())
+        );
+        assert!(responder
+            .respond_to(&request("HEAD", "/missing.txt"))
+            .is_err());
+        assert!(responder.respond_to(&request("POST", "/file.txt")).is_err());
+    }
+}
@@ -1,70 +1,248 @@
 use std::collections::HashMap;
 
 use super::errors::*;
-use super::{HarResponder, Request, ResponderBehaviour, Response};
+use super::spill::{SpillStore, SpilledBody};
+use super::{BodyRewriter, DuplicatePolicy, HarResponder, Request, ResponderBehaviour, Response};
 
 #[derive(Debug)]
 /// Internal state associated to each request
 struct StatefulResponses {
     /// All possible responses for this request
-    responses: Vec<Response>,
+    responses: Vec<StoredResponse>,
     /// How to pick from the set of responses
     behaviour: ResponderBehaviour,
-    /// Current
+    /// Index of the last response given
     last_index: Option<usize>,
+    /// Source the responses were last loaded from
+    source: usize,
+}
+
+#[derive(Debug)]
+/// A response, whose body might have been spilled to disk
+struct StoredResponse {
+    response: Response,
+    spilled: Option<SpilledBody>,
 }
 
 #[derive(Debug)]
 pub struct InMemoryResponder {
     responses: HashMap<Request, StatefulResponses>,
+    behaviour: ResponderBehaviour,
+    spill_store: Option<SpillStore>,
+    duplicates: DuplicatePolicy,
+    /// Source responses are being inserted from, see `next_source`
+    source: usize,
 }
 
 impl InMemoryResponder {
+    /// Responses are inserted as they're loaded outside of tests, see `empty`
+    #[cfg(test)]
     pub fn new<RQ: Into<Request>, RP: Into<Response>>(
         behaviour: ResponderBehaviour,
         iter: impl Iterator<Item = (RQ, RP)>,
     ) -> Self {
-        let mut responses: HashMap<Request, StatefulResponses> = HashMap::new();
+        let mut responder = Self::empty(behaviour);
 
         for (into_req, into_resp) in iter {
-            let stateful_responses =
-                responses
-                    .entry(into_req.into())
-                    .or_insert_with(|| StatefulResponses {
-                        responses: Vec::with_capacity(1),
-                        behaviour: behaviour.clone(),
-                        last_index: None,
-                    });
-            stateful_responses.responses.push(into_resp.into());
+            responder.insert(into_req.into(), into_resp.into());
+        }
+
+        responder
+    }
+
+    /// A responder without responses yet, see `insert`
+    pub fn empty(behaviour: ResponderBehaviour) -> Self {
+        Self {
+            responses: HashMap::new(),
+            behaviour,
+            spill_store: None,
+            duplicates: DuplicatePolicy::Append,
+            source: 0,
+        }
+    }
+
+    /// How to merge requests inserted from different sources
+    pub fn with_duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Start inserting responses from another source (HAR file). Responses
+    /// of the same source always make up a sequence, but requests already
+    /// inserted from previous sources are merged following the duplicate policy.
+    pub fn next_source(&mut self) {
+        self.source += 1;
+    }
+
+    /// Keep large bodies of responses inserted from now on in `spill_store`
+    pub fn with_spill_store(mut self, spill_store: SpillStore) -> Self {
+        self.spill_store = Some(spill_store);
+        self
+    }
+
+    pub fn insert(&mut self, request: Request, response: Response) {
+        let source = self.source;
+        if let Some(state) = self.responses.get_mut(&request) {
+            if state.source != source {
+                match self.duplicates {
+                    DuplicatePolicy::Append => {}
+                    // Before spilling anything of a response to be dropped
+                    DuplicatePolicy::FirstWins => return,
+                    DuplicatePolicy::LastWins => {
+                        for stored in state.responses.drain(..) {
+                            if let (Some(spilled), Some(spill_store)) =
+                                (stored.spilled, &mut self.spill_store)
+                            {
+                                spill_store.release(spilled);
+                            }
+                        }
+                        state.last_index = None;
+                    }
+                }
+                state.source = source;
+            }
         }
 
-        Self { responses }
+        let stored = store(&mut self.spill_store, &request, response);
+        let behaviour = &self.behaviour;
+        self.responses
+            .entry(request)
+            .or_insert_with(|| StatefulResponses {
+                responses: Vec::with_capacity(1),
+                behaviour: behaviour.clone(),
+                last_index: None,
+                source,
+            })
+            .responses
+            .push(stored);
+    }
+
+    /// Rewrite the bodies of every response inserted so far, once all their
+    /// origins are known
+    pub fn rewrite_bodies(&mut self, rewriter: &BodyRewriter) {
+        for (request, state) in self.responses.iter_mut() {
+            for stored in state.responses.iter_mut() {
+                if !rewriter.applies_to(&stored.response) {
+                    continue;
+                }
+
+                let mut response = match load(&mut self.spill_store, stored) {
+                    Ok(response) => response,
+                    Err(_) => continue,
+                };
+                rewriter.rewrite(&mut response);
+                if let (Some(spilled), Some(spill_store)) = (stored.spilled, &mut self.spill_store)
+                {
+                    spill_store.release(spilled);
+                }
+                *stored = store(&mut self.spill_store, request, response);
+            }
+        }
     }
 }
 
-impl HarResponder for InMemoryResponder {
-    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+/// Keep `response`, spilling its body to disk if large
+fn store(
+    spill_store: &mut Option<SpillStore>,
+    request: &Request,
+    mut response: Response,
+) -> StoredResponse {
+    let spilled = match (spill_store, &response.body) {
+        (Some(spill_store), Some(body)) => spill_store.spill(body).unwrap_or_else(|error| {
+            log::warn!("Keeping body of {} in memory: {}", request, error);
+            None
+        }),
+        _ => None,
+    };
+    if spilled.is_some() {
+        response.body = None;
+    }
+
+    StoredResponse { response, spilled }
+}
+
+/// Clone a stored response, reading its body back from disk if needed
+fn load(
+    spill_store: &mut Option<SpillStore>,
+    stored: &StoredResponse,
+) -> Result<Response, ResponderError> {
+    let mut response = stored.response.clone();
+
+    if let (Some(spilled), Some(spill_store)) = (&stored.spilled, spill_store) {
+        response.body = Some(spill_store.read(spilled).map_err(|error| {
+            log::error!("Error reading a spilled body: {}", error);
+            ResponderError::ReadingBody
+        })?);
+    }
+
+    Ok(response)
+}
+
+impl InMemoryResponder {
+    /// Respond with the next response of the sequence, moving on to it if `advance`
+    fn respond(&mut self, request: &Request, advance: bool) -> Result<Response, ResponderError> {
         let state = self
             .responses
             .get_mut(request)
             .ok_or(ResponderError::RequestNotFound)?;
 
-        state
+        let index = state
             .behaviour
             .choose_index(state.last_index, state.responses.len())
+            .ok_or(ResponderError::ResponseNotFound)?;
+        if advance {
+            state.last_index = Some(index);
+        }
+
+        let stored = state
+            .responses
+            .get(index)
+            .ok_or(ResponderError::ResponseNotFound)?;
+        load(&mut self.spill_store, stored)
+    }
+}
+
+impl HarResponder for InMemoryResponder {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond(request, true)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        let state = self
+            .responses
+            .get(request)
+            .ok_or(ResponderError::RequestNotFound)?;
+
+        let length = state.responses.len();
+        let stored = state
+            .behaviour
+            .choose_index(state.last_index, length)
+            // Sequences that ran out still have something wrong to serve
+            .or(state.last_index)
+            .map(|index| (index + 1) % length)
             .and_then(|index| state.responses.get(index))
-            .map(Clone::clone)
-            .ok_or(ResponderError::ResponseNotFound)
+            .ok_or(ResponderError::ResponseNotFound)?;
+        load(&mut self.spill_store, stored)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond(request, false)
+    }
+
+    fn record(&mut self, request: Request, response: Response) {
+        self.insert(request, response)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use assert_matches::assert_matches;
     use test_case::test_case;
     use url::Url;
 
     use crate::req_resp::{
-        HarResponder, InMemoryResponder, Request, ResponderBehaviour, ResponderError, Response,
+        BodyRewriter, DuplicatePolicy, HarResponder, HeadResponder, Header, InMemoryResponder,
+        Request, ResponderBehaviour, ResponderError, Response, SpillStore, Timings,
     };
 
     fn reqs_resp_fixture() -> impl Iterator<Item = (Request, Response)> {
@@ -74,12 +252,15 @@ mod tests {
                     method: "GET".into(),
                     url: Url::parse("http://harplay/path/").unwrap(),
                     original_url: "http://harplay/path/".into(),
+                    origin: None,
                     headers: Vec::new(),
                 },
                 Response {
                     status_code: 200,
                     headers: Vec::new(),
                     body: Some(i.to_string().into()),
+                    timings: Timings::default(),
+                    web_socket_messages: Vec::new(),
                 },
             )
         })
@@ -97,6 +278,7 @@ mod tests {
             method: "GET".into(),
             url: Url::parse("http://harplay/path/").unwrap(),
             original_url: "http://harplay/path/".into(),
+            origin: None,
             headers: Vec::new(),
         };
 
@@ -113,6 +295,8 @@ mod tests {
                             status_code: 200,
                             headers: Vec::new(),
                             body: Some(content.into()),
+                            timings: Timings::default(),
+                            web_socket_messages: Vec::new(),
                         }
                     })
                     .unwrap()
@@ -120,4 +304,152 @@ mod tests {
             );
         }
     }
+
+    #[test_case(SequentialWrapping, &["0", "1", "2", "3", "4", "0"])]
+    #[test_case(SequentialClamping, &["0", "1", "2", "3", "4", "4"])]
+    #[test_case(AlwaysLast, &["4", "4"])]
+    #[test_case(AlwaysFirst, &["0", "0"])]
+    fn it_follows_sequences(behaviour: ResponderBehaviour, expected: &[&str]) {
+        let req = reqs_resp_fixture().next().unwrap().0;
+        let mut responder = InMemoryResponder::new(behaviour, reqs_resp_fixture());
+
+        for content in expected {
+            assert_eq!(
+                responder.respond_to(&req).unwrap().body,
+                Some(content.as_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn it_runs_out_of_responses() {
+        let req = reqs_resp_fixture().next().unwrap().0;
+        let mut responder = InMemoryResponder::new(SequentialOnce, reqs_resp_fixture());
+
+        for content in &["0", "1", "2", "3", "4"] {
+            assert_eq!(
+                responder.respond_to(&req).unwrap().body,
+                Some(content.as_bytes().to_vec())
+            );
+        }
+        assert_matches!(
+            responder.respond_to(&req),
+            Err(ResponderError::ResponseNotFound)
+        );
+    }
+
+    #[test]
+    fn it_responds_with_other_variants() {
+        let req = reqs_resp_fixture().next().unwrap().0;
+        let mut responder = InMemoryResponder::new(SequentialOnce, reqs_resp_fixture());
+
+        assert_eq!(
+            responder.respond_to_other(&req).unwrap().body,
+            Some("1".into())
+        );
+        assert_eq!(responder.respond_to(&req).unwrap().body, Some("0".into()));
+        assert_eq!(
+            responder.respond_to_other(&req).unwrap().body,
+            Some("2".into())
+        );
+
+        for _ in 0..4 {
+            responder.respond_to(&req).unwrap();
+        }
+        assert!(responder.respond_to(&req).is_err());
+        assert_eq!(
+            responder.respond_to_other(&req).unwrap().body,
+            Some("0".into())
+        );
+    }
+
+    #[test_case(DuplicatePolicy::Append, &["0", "1", "2", "3", "4", "a", "b"])]
+    #[test_case(DuplicatePolicy::FirstWins, &["0", "1", "2", "3", "4"])]
+    #[test_case(DuplicatePolicy::LastWins, &["a", "b"])]
+    fn it_merges_sources(duplicates: DuplicatePolicy, expected: &[&str]) {
+        let req = reqs_resp_fixture().next().unwrap().0;
+        let mut responder =
+            InMemoryResponder::new(SequentialOnce, reqs_resp_fixture()).with_duplicates(duplicates);
+
+        responder.next_source();
+        for body in &["a", "b"] {
+            let mut response = reqs_resp_fixture().next().unwrap().1;
+            response.body = Some(body.as_bytes().to_vec());
+            responder.insert(req.clone(), response);
+        }
+
+        for content in expected {
+            assert_eq!(
+                responder.respond_to(&req).unwrap().body,
+                Some(content.as_bytes().to_vec())
+            );
+        }
+        assert!(responder.respond_to(&req).is_err());
+    }
+
+    #[test]
+    fn it_reads_spilled_bodies_back() {
+        let req = reqs_resp_fixture().next().unwrap().0;
+        let mut responder = InMemoryResponder::empty(SequentialWrapping)
+            .with_spill_store(SpillStore::new(4).unwrap());
+
+        for body in &["tiny", "large body", "larger body"] {
+            let mut response = reqs_resp_fixture().next().unwrap().1;
+            response.body = Some(body.as_bytes().to_vec());
+            responder.insert(req.clone(), response);
+        }
+
+        for body in &["tiny", "large body", "larger body", "tiny"] {
+            assert_eq!(
+                responder.respond_to(&req).unwrap().body,
+                Some(body.as_bytes().to_vec())
+            );
+        }
+        assert_eq!(
+            responder.respond_to_other(&req).unwrap().body,
+            Some("larger body".into())
+        );
+    }
+
+    #[test]
+    fn it_rewrites_bodies() {
+        let req = reqs_resp_fixture().next().unwrap().0;
+        let mut responder = InMemoryResponder::empty(SequentialWrapping)
+            .with_spill_store(SpillStore::new(32).unwrap());
+
+        for body in &["https://a.example.com/", "https://a.example.com/large/body"] {
+            let mut response = reqs_resp_fixture().next().unwrap().1;
+            response.headers.push(Header {
+                name: "Content-Type".into(),
+                value: "text/plain".into(),
+            });
+            response.body = Some(body.as_bytes().to_vec());
+            responder.insert(req.clone(), response);
+        }
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("https://a.example.com", "http://localhost:3030");
+        responder.rewrite_bodies(&rewriter);
+
+        for body in &["http://localhost:3030/", "http://localhost:3030/large/body"] {
+            assert_eq!(
+                responder.respond_to(&req).unwrap().body,
+                Some(body.as_bytes().to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn it_responds_with_recorded_responses() {
+        let (req, resp) = reqs_resp_fixture().next().unwrap();
+        let mut responder: Box<dyn HarResponder> = Box::new(HeadResponder::new(
+            InMemoryResponder::empty(SequentialWrapping),
+        ));
+        assert_matches!(
+            responder.respond_to(&req),
+            Err(ResponderError::RequestNotFound)
+        );
+
+        responder.record(req.clone(), resp);
+        assert_eq!(responder.respond_to(&req).unwrap().body, Some("0".into()));
+    }
 }
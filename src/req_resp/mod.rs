@@ -1,13 +1,28 @@
 mod behaviour;
+mod conditional;
+mod cors;
 mod errors;
+mod head;
 mod in_memory;
+mod range;
+mod rewrite;
+mod spill;
+mod web_socket;
 
 use std::convert::TryFrom;
+use std::time::Duration;
 use url::Url;
 
-pub use behaviour::ResponderBehaviour;
+pub use behaviour::{DuplicatePolicy, ResponderBehaviour};
+pub use conditional::{fill_e_tag_from_cache, ConditionalResponder};
+pub use cors::{CorsConfig, CorsResponder};
 pub use errors::*;
+pub use head::HeadResponder;
 pub use in_memory::InMemoryResponder;
+pub use range::RangeResponder;
+pub use rewrite::{parse_origin, BodyRewriter, OriginRewrite, RewriteResponder};
+pub use spill::SpillStore;
+pub use web_socket::{web_socket_messages, WebSocketData, WebSocketMessage};
 
 // Maybe rename these generic names into more specific ones,
 // since we are also dealing with `http`'s types.
@@ -27,55 +42,164 @@ impl From<crate::har::Headers> for Header {
     }
 }
 
+/// Recorded server-side timings of a response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timings {
+    /// Waiting for the first byte
+    pub wait: Duration,
+    /// Receiving the body
+    pub receive: Duration,
+}
+
+impl From<&crate::har::Entries> for Timings {
+    fn from(entry: &crate::har::Entries) -> Self {
+        // HAR uses `-1` for timings that don't apply
+        let millis = |value: Option<f64>| value.filter(|value| *value >= 0.0);
+
+        let receive = millis(entry.timings.receive).unwrap_or(0.0);
+        let wait = millis(entry.timings.wait).unwrap_or_else(|| (entry.time - receive).max(0.0));
+
+        Self {
+            wait: Duration::from_secs_f64(wait / 1000.0),
+            receive: Duration::from_secs_f64(receive / 1000.0),
+        }
+    }
+}
+
 /// Very simple representation of a recorded response
 #[derive(Debug, Clone)]
 pub struct Response {
     pub status_code: u16,
     pub headers: Vec<Header>,
     pub body: Option<Vec<u8>>,
+    pub timings: Timings,
+    /// Frames to replay once upgraded to a WebSocket
+    pub web_socket_messages: Vec<WebSocketMessage>,
+}
+
+impl Response {
+    /// Value of the first header named `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    /// Replace all headers named `name` (case-insensitive) with a single one
+    pub fn set_header<V: Into<String>>(&mut self, name: &str, value: V) {
+        self.remove_header(name);
+        self.headers.push(Header {
+            name: name.into(),
+            value: value.into(),
+        });
+    }
+
+    /// Remove all headers named `name` (case-insensitive)
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers
+            .retain(|header| !header.name.eq_ignore_ascii_case(name));
+    }
 }
 
 impl From<crate::har::Response> for Response {
     fn from(response: crate::har::Response) -> Self {
+        let is_base64 = response
+            .content
+            .encoding
+            .as_ref()
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"));
+
+        let body = response.content.text.map(|text| {
+            if is_base64 {
+                // Binary contents (images, media...) are recorded base64-encoded
+                base64::decode(text.trim()).unwrap_or_else(|error| {
+                    log::warn!("Serving undecoded body, invalid base64: {}", error);
+                    text.into()
+                })
+            } else {
+                text.into()
+            }
+        });
+
         Self {
             status_code: response.status as u16,
-            body: response.content.text.map(Into::into),
+            body,
             headers: response.headers.iter().cloned().map(From::from).collect(),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
         }
     }
 }
 
 /// Very simple representation of a recorded request
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request {
     pub method: String,
+    /// Normalized to `http://harplay/PATH?QUERY`, to match requests with the
+    /// recorded ones whatever server they were made to. Nothing is ever sent
+    /// to it: responses go back over the connection the request came in on,
+    /// TLS or not.
     pub url: Url,
     pub original_url: String,
+    /// Origin (`scheme://host[:port]`) before normalization, when known
+    pub origin: Option<String>,
     pub headers: Vec<Header>,
 }
 
+impl Request {
+    /// Value of the first header named `name` (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    /// The URL as made to its origin, if known, for matching patterns users
+    /// write against recorded URLs (the normalized one always says `harplay`)
+    pub fn public_url(&self) -> String {
+        let path = &self.url[url::Position::BeforePath..url::Position::AfterQuery];
+        match &self.origin {
+            Some(origin) => format!("{}{}", origin, path),
+            None => path.into(),
+        }
+    }
+
+    /// Tell this request apart from the same one to other origins, keeping
+    /// the host and port of `origin` in the normalized URL
+    pub fn scope_to(&mut self, origin: &str) -> Result<(), IntoRequestError> {
+        let origin = Url::parse(origin).map_err(|_| IntoRequestError::ParsingUrl)?;
+        self.url
+            .set_host(origin.host_str())
+            .map_err(|_| IntoRequestError::ReplacingHost)?;
+        self.url
+            .set_port(origin.port_or_known_default())
+            .map_err(|_| IntoRequestError::ReplacingHost)
+    }
+}
+
 impl std::fmt::Display for Request {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Request {} {}", self.method, self.original_url)
     }
 }
 
+// Headers are left out of both equality and hashing: clients never send the
+// exact same headers that were recorded (`Origin`, cookies, user agents...),
+// and `Hash` has to agree with `Eq` for lookups to work at all.
 impl PartialEq for Request {
     fn eq(&self, other: &Self) -> bool {
-        self.url == other.url
+        self.method == other.method && self.url == other.url
     }
 }
 
 impl Eq for Request {}
 
 impl std::hash::Hash for Request {
-    fn hash<'a, H: std::hash::Hasher>(&'a self, state: &mut H) {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.method.hash(state);
         self.url.hash(state);
-        for header in self.headers.iter() {
-            header.name.hash(state);
-            header.value.hash(state);
-        }
     }
 }
 
@@ -116,9 +240,19 @@ impl<B> TryFrom<http::Request<B>> for Request {
             return Err(IntoRequestError::NonHttpScheme);
         }
 
+        let origin = original_uri
+            .authority()
+            .map(|_| url.origin().ascii_serialization());
+
+        // The port goes along with the host: HTTP/2 requests carry the
+        // server's, and recordings that of wherever they were made
         url.set_host(Some("harplay"))
             .map_err(|_| IntoRequestError::ReplacingHost)?;
+        url.set_port(None)
+            .map_err(|_| IntoRequestError::ReplacingHost)?;
 
+        // Only for matching, see `Request::url`; HTTPS requests stay HTTPS
+        // and their origin says so
         url.set_scheme("http")
             .map_err(|_| IntoRequestError::ReplacingScheme)?;
 
@@ -142,6 +276,7 @@ impl<B> TryFrom<http::Request<B>> for Request {
             method: req.method().as_str().into(),
             url,
             original_url: original_uri.to_string(),
+            origin,
             headers,
         })
     }
@@ -154,13 +289,19 @@ impl TryFrom<crate::har::Request> for Request {
         let original_url = req.url;
         let mut url = Url::parse(&original_url).map_err(|_| IntoRequestError::ParsingUrl)?;
 
+        // WebSocket connections are recorded with their `ws`/`wss` URLs, but
+        // clients open them with a regular HTTP request
         let current_scheme = url.scheme();
-        if !(current_scheme == "http" || current_scheme == "https") {
+        if !["http", "https", "ws", "wss"].contains(&current_scheme) {
             return Err(IntoRequestError::NonHttpScheme);
         }
 
+        let origin = Some(url.origin().ascii_serialization());
+
         url.set_host(Some("harplay"))
             .map_err(|_| IntoRequestError::ReplacingHost)?;
+        url.set_port(None)
+            .map_err(|_| IntoRequestError::ReplacingHost)?;
 
         url.set_scheme("http")
             .map_err(|_| IntoRequestError::ReplacingScheme)?;
@@ -172,6 +313,7 @@ impl TryFrom<crate::har::Request> for Request {
             method: req.method,
             url,
             original_url,
+            origin,
             headers,
         })
     }
@@ -179,6 +321,43 @@ impl TryFrom<crate::har::Request> for Request {
 
 pub trait HarResponder {
     fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError>;
+
+    /// Respond with a different recorded response than `respond_to` would
+    /// (when there is more than one), without advancing any state.
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_to(request)
+    }
+
+    /// Respond with what `respond_to` would, without advancing any state;
+    /// wrapping responders pass it on.
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError>;
+
+    /// Respond to `request` with `response` from now on, for responders that
+    /// keep responses around; wrapping responders pass it on. Not defaulted,
+    /// so a wrapper can't swallow it by forgetting to.
+    fn record(&mut self, request: Request, response: Response);
+}
+
+/// Either `HarResponder::respond_to` or `HarResponder::respond_to_other`, so
+/// wrapping responders can implement both at once.
+type Respond<R> = fn(&mut R, &Request) -> Result<Response, ResponderError>;
+
+impl<R: HarResponder + ?Sized> HarResponder for Box<R> {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        (**self).respond_to(request)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        (**self).respond_to_other(request)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        (**self).peek(request)
+    }
+
+    fn record(&mut self, request: Request, response: Response) {
+        (**self).record(request, response)
+    }
 }
 
 impl From<Response> for http::Response<hyper::Body> {
@@ -209,3 +388,199 @@ impl From<Response> for http::Response<hyper::Body> {
             .unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("GET", "/a?b=c", true; "same request")]
+    #[test_case("GET", "/a?b=d", false; "other query")]
+    #[test_case("POST", "/a?b=c", false; "other method")]
+    fn matches_by_method_and_url(method: &str, path: &str, expected: bool) {
+        let recorded = Request::try_from(crate::har::Request {
+            method: "GET".into(),
+            url: "https://api.example.com/a?b=c".into(),
+            headers: vec![crate::har::Headers {
+                name: "User-Agent".into(),
+                value: "Recorder/1.0".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let incoming = Request::try_from(
+            http::Request::builder()
+                .method(method)
+                .uri(path)
+                .header("User-Agent", "curl/7.68.0")
+                .header("Origin", "http://localhost:8080")
+                .body(())
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(incoming == recorded, expected);
+        // Lookups hash, which has to agree
+        assert_eq!(
+            vec![recorded]
+                .into_iter()
+                .collect::<HashSet<_>>()
+                .contains(&incoming),
+            expected
+        );
+    }
+
+    #[test_case("http://localhost:8080/a?b=c", "/a?b=c"; "recorded port, origin form")]
+    #[test_case("http://localhost/a?b=c", "/a?b=c"; "no port, origin form")]
+    #[test_case("https://example.com:443/a?b=c", "https://example.com/a?b=c"; "default port")]
+    #[test_case("http://localhost:8080/a?b=c", "https://127.0.0.1:3030/a?b=c"; "other authority")]
+    #[test_case("http://localhost/a?b=c", "http://localhost:3030/a?b=c"; "incoming port")]
+    fn matches_whatever_the_port(recorded: &str, incoming: &str) {
+        let recorded = Request::try_from(crate::har::Request {
+            method: "GET".into(),
+            url: recorded.into(),
+            ..Default::default()
+        })
+        .unwrap();
+        let incoming = Request::try_from(http::Request::get(incoming).body(()).unwrap()).unwrap();
+
+        assert_eq!(incoming, recorded);
+        assert_eq!(incoming.url.port(), None);
+    }
+
+    #[test]
+    fn keeps_ports_apart_when_scoped() {
+        let request = |url: &str| {
+            Request::try_from(crate::har::Request {
+                method: "GET".into(),
+                url: url.into(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (mut first, mut second) = (
+            request("http://api.example.com:8080/a"),
+            request("http://api.example.com:9090/a"),
+        );
+        first
+            .scope_to(first.origin.clone().unwrap().as_str())
+            .unwrap();
+        second
+            .scope_to(second.origin.clone().unwrap().as_str())
+            .unwrap();
+
+        assert_eq!(first.url.as_str(), "http://api.example.com:8080/a");
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn normalizes_hosts_and_ports() {
+        let incoming = Request::try_from(
+            http::Request::get("https://127.0.0.1:3030/a?b=c")
+                .body(())
+                .unwrap(),
+        )
+        .unwrap();
+        let recorded = Request::try_from(crate::har::Request {
+            method: "GET".into(),
+            url: "http://localhost:8080/a?b=c".into(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(incoming.url.as_str(), "http://harplay/a?b=c");
+        assert_eq!(incoming.origin.as_deref(), Some("https://127.0.0.1:3030"));
+        assert_eq!(incoming, recorded);
+    }
+
+    #[test]
+    fn public_urls() {
+        let mut request = Request::try_from(crate::har::Request {
+            method: "GET".into(),
+            url: "https://api.example.com:8443/a?b=c#d".into(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(request.public_url(), "https://api.example.com:8443/a?b=c");
+
+        request.origin = None;
+        assert_eq!(request.public_url(), "/a?b=c");
+    }
+
+    #[test]
+    fn scopes_to_origins() {
+        let request = || {
+            Request::try_from(crate::har::Request {
+                method: "GET".into(),
+                url: "https://api.example.com/a".into(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (mut api, mut cdn, mut insecure_api) = (request(), request(), request());
+        api.scope_to("https://api.example.com").unwrap();
+        cdn.scope_to("https://cdn.example.com").unwrap();
+        insecure_api.scope_to("http://api.example.com").unwrap();
+
+        assert_eq!(api.url.as_str(), "http://api.example.com:443/a");
+        assert_ne!(api, request());
+        assert_ne!(api, cdn);
+        assert_ne!(api, insecure_api);
+    }
+
+    #[test]
+    fn decodes_base64_contents() {
+        let mut recorded = crate::har::Response {
+            status: 200,
+            content: crate::har::Content {
+                text: Some("AAEC/w==".into()),
+                encoding: Some("base64".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Response::from(recorded.clone()).body,
+            Some(vec![0, 1, 2, 255])
+        );
+
+        recorded.content.encoding = None;
+        assert_eq!(Response::from(recorded).body, Some("AAEC/w==".into()));
+    }
+
+    #[test]
+    fn timings_from_entries() {
+        let mut entry = crate::har::Entries {
+            time: 150.0,
+            timings: crate::har::Timings {
+                wait: Some(100.0),
+                receive: Some(25.5),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Timings::from(&entry),
+            Timings {
+                wait: Duration::from_millis(100),
+                receive: Duration::from_micros(25_500),
+            }
+        );
+
+        entry.timings.wait = Some(-1.0);
+        entry.timings.receive = None;
+        assert_eq!(
+            Timings::from(&entry),
+            Timings {
+                wait: Duration::from_millis(150),
+                receive: Duration::from_millis(0),
+            }
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::ops::RangeInclusive;
+
+use super::errors::*;
+use super::{HarResponder, Request, Respond, Response};
+
+const BOUNDARY: &str = "HARPLAY_BYTERANGES";
+
+/// Answers `Range` requests with `206 Partial Content` (single or
+/// `multipart/byteranges`), or `416 Range Not Satisfiable` for bad ranges.
+#[derive(Debug)]
+pub struct RangeResponder<R> {
+    inner: R,
+}
+
+impl<R: HarResponder> RangeResponder<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    fn respond_with(
+        &mut self,
+        request: &Request,
+        respond: Respond<R>,
+    ) -> Result<Response, ResponderError> {
+        let response = respond(&mut self.inner, request)?;
+
+        if !request.method.eq_ignore_ascii_case("GET")
+            || response.status_code != 200
+            || response.body.is_none()
+        {
+            return Ok(response);
+        }
+
+        let range = match request.header("range") {
+            Some(range) if if_range_matches(request, &response) => range,
+            _ => return Ok(response),
+        };
+
+        let length = response.body.as_ref().map_or(0, Vec::len);
+        Ok(match parse_ranges(range, length) {
+            // Unparseable `Range` headers are ignored (RFC 7233, section 3.1)
+            None => response,
+            Some(ranges) if ranges.is_empty() => not_satisfiable(response, length),
+            Some(ranges) => partial_content(response, &ranges),
+        })
+    }
+}
+
+impl<R: HarResponder> HarResponder for RangeResponder<R> {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::respond_to_other)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.respond_with(request, R::peek)
+    }
+
+    fn record(&mut self, request: Request, response: Response) {
+        self.inner.record(request, response)
+    }
+}
+
+/// `If-Range` only allows partial responses for the same representation
+fn if_range_matches(request: &Request, response: &Response) -> bool {
+    match request.header("if-range").map(str::trim) {
+        None => true,
+        Some(validator) if validator.starts_with('"') => {
+            response.header("etag").map(str::trim) == Some(validator)
+        }
+        Some(validator) => response.header("last-modified").map(str::trim) == Some(validator),
+    }
+}
+
+/// Parse a `bytes=...` range set against a body of `length` bytes.
+///
+/// Returns `None` when the header can't be parsed, and the satisfiable ranges
+/// otherwise (possibly none of them).
+fn parse_ranges(header: &str, length: usize) -> Option<Vec<RangeInclusive<usize>>> {
+    let mut parts = header.trim().splitn(2, '=');
+    if !parts.next()?.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in parts.next()?.split(',').map(str::trim) {
+        let mut bounds = spec.splitn(2, '-');
+        let (start, end) = (bounds.next()?.trim(), bounds.next()?.trim());
+
+        let range = if start.is_empty() {
+            let suffix = end.parse::<usize>().ok()?;
+            if suffix == 0 || length == 0 {
+                continue;
+            }
+            length.saturating_sub(suffix)..=length - 1
+        } else {
+            let start = start.parse::<usize>().ok()?;
+            let end = if end.is_empty() {
+                None
+            } else {
+                Some(end.parse::<usize>().ok()?)
+            };
+            if end.is_some_and(|end| end < start) {
+                return None;
+            }
+            if start >= length {
+                continue;
+            }
+            start..=end.map_or(length - 1, |end| end.min(length - 1))
+        };
+
+        ranges.push(range);
+    }
+
+    Some(ranges)
+}
+
+fn not_satisfiable(mut response: Response, length: usize) -> Response {
+    response.status_code = 416;
+    response.body = None;
+    response.remove_header("content-type");
+    response.remove_header("content-encoding");
+    response.set_header("content-range", format!("bytes */{}", length));
+    response.set_header("content-length", "0");
+    response
+}
+
+fn partial_content(mut response: Response, ranges: &[RangeInclusive<usize>]) -> Response {
+    let body = response.body.take().unwrap_or_default();
+    let length = body.len();
+    let content_range = |range: &RangeInclusive<usize>| {
+        format!("bytes {}-{}/{}", range.start(), range.end(), length)
+    };
+
+    response.status_code = 206;
+    response.set_header("accept-ranges", "bytes");
+
+    let partial = if let [range] = ranges {
+        response.set_header("content-range", content_range(range));
+        body[range.clone()].to_vec()
+    } else {
+        let content_type = response.header("content-type").map(String::from);
+        let mut multipart = Vec::new();
+
+        for range in ranges {
+            multipart.extend_from_slice(format!("\r\n--{}\r\n", BOUNDARY).as_bytes());
+            if let Some(content_type) = content_type.as_ref() {
+                multipart
+                    .extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+            }
+            multipart.extend_from_slice(
+                format!("Content-Range: {}\r\n\r\n", content_range(range)).as_bytes(),
+            );
+            multipart.extend_from_slice(&body[range.clone()]);
+        }
+        multipart.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        response.remove_header("content-range");
+        response.set_header(
+            "content-type",
+            format!("multipart/byteranges; boundary={}", BOUNDARY),
+        );
+        multipart
+    };
+
+    response.set_header("content-length", partial.len().to_string());
+    response.body = Some(partial);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+    use url::Url;
+
+    use super::*;
+    use crate::req_resp::{Header, InMemoryResponder, ResponderBehaviour, Timings};
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".into(),
+            url: Url::parse("http://harplay/video.mp4").unwrap(),
+            original_url: "http://harplay/video.mp4".into(),
+            origin: None,
+            headers: headers
+                .iter()
+                .map(|(name, value)| Header {
+                    name: (*name).into(),
+                    value: (*value).into(),
+                })
+                .collect(),
+        }
+    }
+
+    fn responder() -> RangeResponder<InMemoryResponder> {
+        let recorded = Response {
+            status_code: 200,
+            headers: vec![
+                Header {
+                    name: "Content-Type".into(),
+                    value: "video/mp4".into(),
+                },
+                Header {
+                    name: "Content-Length".into(),
+                    value: "10".into(),
+                },
+                Header {
+                    name: "ETag".into(),
+                    value: "\"v1\"".into(),
+                },
+            ],
+            body: Some("0123456789".into()),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        };
+        RangeResponder::new(InMemoryResponder::new(
+            ResponderBehaviour::AlwaysFirst,
+            vec![(request(&[]), recorded)].into_iter(),
+        ))
+    }
+
+    #[test_case("bytes=0-3", 10, Some(vec![0..=3]))]
+    #[test_case("bytes=5-", 10, Some(vec![5..=9]))]
+    #[test_case("bytes=-3", 10, Some(vec![7..=9]))]
+    #[test_case("bytes=-30", 10, Some(vec![0..=9]))]
+    #[test_case("bytes=8-20", 10, Some(vec![8..=9]))]
+    #[test_case("bytes=0-1, 4-5", 10, Some(vec![0..=1, 4..=5]))]
+    #[test_case("bytes=10-", 10, Some(vec![]))]
+    #[test_case("bytes=20-30, 5-6", 10, Some(vec![5..=6]))]
+    #[test_case("bytes=3-1", 10, None)]
+    #[test_case("bytes=a-b", 10, None)]
+    #[test_case("items=0-1", 10, None)]
+    fn range_parsing(header: &str, length: usize, expected: Option<Vec<RangeInclusive<usize>>>) {
+        assert_eq!(parse_ranges(header, length), expected);
+    }
+
+    #[test]
+    fn single_range() {
+        let response = responder()
+            .respond_to(&request(&[("Range", "bytes=2-5")]))
+            .unwrap();
+
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.header("content-range"), Some("bytes 2-5/10"));
+        assert_eq!(response.header("content-length"), Some("4"));
+        assert_eq!(response.header("content-type"), Some("video/mp4"));
+        assert_eq!(response.body, Some("2345".into()));
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        let response = responder()
+            .respond_to(&request(&[("Range", "bytes=0-1,-2")]))
+            .unwrap();
+
+        let expected = "\r\n--HARPLAY_BYTERANGES\r\n\
+            Content-Type: video/mp4\r\n\
+            Content-Range: bytes 0-1/10\r\n\r\n\
+            01\
+            \r\n--HARPLAY_BYTERANGES\r\n\
+            Content-Type: video/mp4\r\n\
+            Content-Range: bytes 8-9/10\r\n\r\n\
+            89\
+            \r\n--HARPLAY_BYTERANGES--\r\n";
+
+        assert_eq!(response.status_code, 206);
+        assert_eq!(
+            response.header("content-type"),
+            Some("multipart/byteranges; boundary=HARPLAY_BYTERANGES")
+        );
+        assert_eq!(
+            response.header("content-length"),
+            Some(expected.len().to_string().as_str())
+        );
+        assert_eq!(response.body, Some(expected.into()));
+    }
+
+    #[test]
+    fn unsatisfiable_range() {
+        let response = responder()
+            .respond_to(&request(&[("Range", "bytes=100-")]))
+            .unwrap();
+
+        assert_eq!(response.status_code, 416);
+        assert_eq!(response.header("content-range"), Some("bytes */10"));
+        assert_eq!(response.body, None);
+    }
+
+    #[test_case(&[], 200)]
+    #[test_case(&[("Range", "bytes=9-1")], 200)]
+    #[test_case(&[("Range", "bytes=0-1"), ("If-Range", "\"v1\"")], 206)]
+    #[test_case(&[("Range", "bytes=0-1"), ("If-Range", "\"v0\"")], 200)]
+    fn full_responses(headers: &[(&str, &str)], status_code: u16) {
+        let response = responder().respond_to(&request(headers)).unwrap();
+        assert_eq!(response.status_code, status_code);
+    }
+}
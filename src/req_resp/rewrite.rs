@@ -0,0 +1,345 @@
+use std::str::FromStr;
+
+use url::Url;
+
+use super::errors::*;
+use super::{HarResponder, Request, Response};
+
+/// A recorded origin to rewrite, and optionally the origin to rewrite it to
+/// (parsed from `ORIGIN[=TARGET]`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginRewrite {
+    pub origin: String,
+    pub target: Option<String>,
+}
+
+/// Normalize an origin URL to `scheme://host[:port]`
+pub fn parse_origin(s: &str) -> Result<String, &'static str> {
+    let url = Url::parse(s).map_err(|_| "Invalid origin URL")?;
+    match url.origin() {
+        origin @ url::Origin::Tuple(..) => Ok(origin.ascii_serialization()),
+        url::Origin::Opaque(_) => Err("Origin must be an HTTP/HTTPS URL"),
+    }
+}
+
+impl FromStr for OriginRewrite {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let origin = parse_origin(parts.next().unwrap_or_default())?;
+        let target = parts.next().map(parse_origin).transpose()?;
+        Ok(Self { origin, target })
+    }
+}
+
+/// Replaces absolute references to recorded origins inside textual response
+/// bodies, so follow-up requests also land on harplay.
+#[derive(Debug, Default)]
+pub struct BodyRewriter {
+    /// `(recorded origin, replacement origin)` pairs
+    rules: Vec<(String, String)>,
+}
+
+impl BodyRewriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_origin<O: Into<String>, T: Into<String>>(&mut self, origin: O, target: T) {
+        let origin = origin.into();
+        if self.rules.iter().any(|(existing, _)| *existing == origin) {
+            return;
+        }
+        self.rules.push((origin, target.into()));
+        // Longer origins first, so `http://a.com:8080` is not clobbered by `http://a.com`
+        self.rules
+            .sort_by_key(|(origin, _)| std::cmp::Reverse(origin.len()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `rewrite` might change `response`, going by its headers
+    pub fn applies_to(&self, response: &Response) -> bool {
+        !self.rules.is_empty() && response.header("content-type").is_some_and(is_textual)
+    }
+
+    pub fn rewrite(&self, response: &mut Response) {
+        if !self.applies_to(response) {
+            return;
+        }
+
+        let text = match response
+            .body
+            .as_ref()
+            .and_then(|body| std::str::from_utf8(body).ok())
+        {
+            Some(text) => text,
+            None => return,
+        };
+
+        let mut rewritten = text.to_string();
+        for (origin, target) in self.rules.iter() {
+            rewritten = replace_origin(&rewritten, origin, target);
+            // JSON encoders commonly escape forward slashes
+            rewritten =
+                replace_origin(&rewritten, &escape_slashes(origin), &escape_slashes(target));
+        }
+
+        if rewritten != text {
+            if response.header("content-length").is_some() {
+                response.set_header("content-length", rewritten.len().to_string());
+            }
+            response.body = Some(rewritten.into());
+        }
+    }
+}
+
+/// Rewrites the bodies of responses recorded while serving (as when passing
+/// requests through), like those loaded from HAR files were, see
+/// `InMemoryResponder::rewrite_bodies`.
+#[derive(Debug)]
+pub struct RewriteResponder<R> {
+    inner: R,
+    rewriter: BodyRewriter,
+}
+
+impl<R: HarResponder> RewriteResponder<R> {
+    pub fn new(inner: R, rewriter: BodyRewriter) -> Self {
+        Self { inner, rewriter }
+    }
+}
+
+impl<R: HarResponder> HarResponder for RewriteResponder<R> {
+    fn respond_to(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.inner.respond_to(request)
+    }
+
+    fn respond_to_other(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.inner.respond_to_other(request)
+    }
+
+    fn peek(&mut self, request: &Request) -> Result<Response, ResponderError> {
+        self.inner.peek(request)
+    }
+
+    fn record(&mut self, request: Request, mut response: Response) {
+        self.rewriter.rewrite(&mut response);
+        self.inner.record(request, response)
+    }
+}
+
+/// Replace the occurrences of `origin` that are whole origins, and not the
+/// start of a longer host (`https://a.com.evil.net`) or of a port (`https://a.com:8443`)
+fn replace_origin(text: &str, origin: &str, target: &str) -> String {
+    let mut rewritten = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(origin) {
+        let end = start + origin.len();
+        rewritten.push_str(&rest[..start]);
+        if rest[end..].chars().next().is_none_or(ends_origin) {
+            rewritten.push_str(target);
+        } else {
+            rewritten.push_str(origin);
+        }
+        rest = &rest[end..];
+    }
+
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Whether `c` can follow an origin in a URL, in quoted text or in markup:
+/// backslashes start the escaped slashes and quotes of JSON strings, the
+/// rest close CSS `url()`s, separate `srcset` candidates and CSS or HTML
+/// attribute values, and start tags and entities like `&amp;`
+fn ends_origin(c: char) -> bool {
+    matches!(
+        c,
+        '/' | '"' | '\'' | '?' | '#' | '\\' | ')' | ',' | ';' | '<' | '&'
+    ) || c.is_whitespace()
+}
+
+fn escape_slashes(s: &str) -> String {
+    s.replace('/', "\\/")
+}
+
+/// Whether a `Content-Type` value describes a body we can safely rewrite as text
+fn is_textual(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime.starts_with("text/")
+        || mime.ends_with("/json")
+        || mime.ends_with("+json")
+        || mime.ends_with("/xml")
+        || mime.ends_with("+xml")
+        || mime.ends_with("/javascript")
+        || mime.ends_with("/x-javascript")
+        || mime.ends_with("/ecmascript")
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::req_resp::{Header, InMemoryResponder, ResponderBehaviour, Timings};
+
+    fn response(content_type: &str, body: &str) -> Response {
+        Response {
+            status_code: 200,
+            headers: vec![
+                Header {
+                    name: "Content-Type".into(),
+                    value: content_type.into(),
+                },
+                Header {
+                    name: "Content-Length".into(),
+                    value: body.len().to_string(),
+                },
+            ],
+            body: Some(body.into()),
+            timings: Timings::default(),
+            web_socket_messages: Vec::new(),
+        }
+    }
+
+    #[test_case("https://api.example.com", Ok(("https://api.example.com", None)))]
+    #[test_case("https://api.example.com:443/", Ok(("https://api.example.com", None)))]
+    #[test_case("https://cdn.example.com=http://localhost:3031", Ok(("https://cdn.example.com", Some("http://localhost:3031"))))]
+    #[test_case("api.example.com", Err(()))]
+    #[test_case("data:text/plain,hi", Err(()))]
+    fn origin_rewrite_parsing(input: &str, expected: Result<(&str, Option<&str>), ()>) {
+        assert_eq!(
+            input.parse::<OriginRewrite>().map_err(|_| ()),
+            expected.map(|(origin, target)| OriginRewrite {
+                origin: origin.into(),
+                target: target.map(Into::into),
+            })
+        );
+    }
+
+    #[test_case("text/html; charset=utf-8", true)]
+    #[test_case("application/json", true)]
+    #[test_case("application/vnd.api+json", true)]
+    #[test_case("application/javascript", true)]
+    #[test_case("image/svg+xml", true)]
+    #[test_case("image/png", false)]
+    #[test_case("application/octet-stream", false)]
+    fn textual_mime_types(content_type: &str, expected: bool) {
+        assert_eq!(is_textual(content_type), expected);
+    }
+
+    #[test]
+    fn rewrites_textual_bodies() {
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("https://api.example.com", "http://127.0.0.1:3030");
+
+        let mut resp = response(
+            "application/json",
+            r#"{"next":"https://api.example.com/page/2","alt":"https:\/\/api.example.com\/x"}"#,
+        );
+        rewriter.rewrite(&mut resp);
+
+        let expected =
+            r#"{"next":"http://127.0.0.1:3030/page/2","alt":"http:\/\/127.0.0.1:3030\/x"}"#;
+        assert_eq!(resp.body.as_deref(), Some(expected.as_bytes()));
+        assert_eq!(
+            resp.header("content-length"),
+            Some(expected.len().to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn leaves_binary_bodies_alone() {
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("https://api.example.com", "http://127.0.0.1:3030");
+
+        let mut resp = response("image/png", "https://api.example.com");
+        rewriter.rewrite(&mut resp);
+
+        assert_eq!(resp.body.as_deref(), Some(&b"https://api.example.com"[..]));
+    }
+
+    #[test]
+    fn prefers_longest_origin() {
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("http://a.example.com", "http://one");
+        rewriter.add_origin("http://a.example.com:8080", "http://two");
+
+        let mut resp = response(
+            "text/plain",
+            "http://a.example.com:8080/ http://a.example.com/",
+        );
+        rewriter.rewrite(&mut resp);
+
+        assert_eq!(resp.body.as_deref(), Some(&b"http://two/ http://one/"[..]));
+    }
+
+    #[test_case("https://api.example.com.evil.net/x"; "longer host")]
+    #[test_case("https://api.example.com:8443/x"; "other port")]
+    #[test_case("https://api.example.comics/x"; "longer name")]
+    fn leaves_other_origins_alone(body: &str) {
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("https://api.example.com", "http://127.0.0.1:3030");
+
+        let mut resp = response("text/plain", body);
+        rewriter.rewrite(&mut resp);
+
+        assert_eq!(resp.body.as_deref(), Some(body.as_bytes()));
+    }
+
+    #[test_case("https://api.example.com", "http://127.0.0.1:3030"; "end of text")]
+    #[test_case("'https://api.example.com' ", "'http://127.0.0.1:3030' "; "quoted")]
+    #[test_case("https://api.example.com?a#b", "http://127.0.0.1:3030?a#b"; "query and fragment")]
+    #[test_case("https://api.example.com\nx", "http://127.0.0.1:3030\nx"; "whitespace")]
+    #[test_case("url(https://api.example.com)", "url(http://127.0.0.1:3030)"; "css url")]
+    #[test_case("https://api.example.com 1x,https://api.example.com,x", "http://127.0.0.1:3030 1x,http://127.0.0.1:3030,x"; "srcset")]
+    #[test_case("a=https://api.example.com;b", "a=http://127.0.0.1:3030;b"; "semicolon")]
+    #[test_case("<a>https://api.example.com</a>", "<a>http://127.0.0.1:3030</a>"; "tag")]
+    #[test_case("?u=https://api.example.com&amp;v", "?u=http://127.0.0.1:3030&amp;v"; "entity")]
+    fn rewrites_delimited_origins(body: &str, expected: &str) {
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("https://api.example.com", "http://127.0.0.1:3030");
+
+        let mut resp = response("text/plain", body);
+        rewriter.rewrite(&mut resp);
+
+        assert_eq!(resp.body.as_deref(), Some(expected.as_bytes()));
+    }
+
+    #[test]
+    fn rewrites_recorded_responses() {
+        let request = Request {
+            method: "GET".into(),
+            url: url::Url::parse("http://harplay/").unwrap(),
+            original_url: "https://api.example.com/".into(),
+            origin: Some("https://api.example.com".into()),
+            headers: Vec::new(),
+        };
+        let mut rewriter = BodyRewriter::new();
+        rewriter.add_origin("https://api.example.com", "http://127.0.0.1:3030");
+
+        let mut responder = RewriteResponder::new(
+            InMemoryResponder::empty(ResponderBehaviour::AlwaysFirst),
+            rewriter,
+        );
+        responder.record(
+            request.clone(),
+            response("text/html", "<a href=\"https://api.example.com/x\">"),
+        );
+
+        assert_eq!(
+            responder.respond_to(&request).unwrap().body.as_deref(),
+            Some(&b"<a href=\"http://127.0.0.1:3030/x\">"[..])
+        );
+    }
+}
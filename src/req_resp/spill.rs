@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{Read, Result as IoResult, Seek, SeekFrom, Write};
+
+/// Keeps response bodies over a size threshold in a temporary file instead of
+/// in memory; the file goes away with the store.
+#[derive(Debug)]
+pub struct SpillStore {
+    file: File,
+    length: u64,
+    threshold: usize,
+    /// Regions of released bodies, by offset, for new bodies to reuse
+    free: Vec<SpilledBody>,
+}
+
+/// Where a body ended up in a `SpillStore`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpilledBody {
+    offset: u64,
+    length: usize,
+}
+
+impl SpillStore {
+    /// Spill bodies longer than `threshold` bytes
+    pub fn new(threshold: usize) -> IoResult<Self> {
+        Ok(Self {
+            file: tempfile::tempfile()?,
+            length: 0,
+            threshold,
+            free: Vec::new(),
+        })
+    }
+
+    /// Write `body` to disk if it is over the threshold
+    pub fn spill(&mut self, body: &[u8]) -> IoResult<Option<SpilledBody>> {
+        if body.len() <= self.threshold {
+            return Ok(None);
+        }
+
+        let spilled = SpilledBody {
+            offset: self.allocate(body.len()),
+            length: body.len(),
+        };
+        // Reads move the cursor around
+        self.file.seek(SeekFrom::Start(spilled.offset))?;
+        if let Err(error) = self.file.write_all(body) {
+            self.release(spilled);
+            return Err(error);
+        }
+
+        Ok(Some(spilled))
+    }
+
+    /// Make the region of a body no longer needed available to new ones
+    pub fn release(&mut self, spilled: SpilledBody) {
+        let index = self
+            .free
+            .iter()
+            .position(|free| free.offset > spilled.offset)
+            .unwrap_or(self.free.len());
+        self.free.insert(index, spilled);
+
+        // Merge with the neighbouring regions, so they fit larger bodies
+        if index + 1 < self.free.len() && self.free[index].end() == self.free[index + 1].offset {
+            self.free[index].length += self.free.remove(index + 1).length;
+        }
+        if index > 0 && self.free[index - 1].end() == self.free[index].offset {
+            self.free[index - 1].length += self.free.remove(index).length;
+        }
+    }
+
+    /// Offset to write `length` bytes at: the first free region fitting them,
+    /// or the end of the file
+    fn allocate(&mut self, length: usize) -> u64 {
+        match self.free.iter().position(|free| free.length >= length) {
+            Some(index) => {
+                let free = &mut self.free[index];
+                let offset = free.offset;
+                free.offset += length as u64;
+                free.length -= length;
+                if free.length == 0 {
+                    self.free.remove(index);
+                }
+                offset
+            }
+            None => {
+                let offset = self.length;
+                self.length += length as u64;
+                offset
+            }
+        }
+    }
+
+    pub fn read(&mut self, spilled: &SpilledBody) -> IoResult<Vec<u8>> {
+        let mut body = vec![0; spilled.length];
+        self.file.seek(SeekFrom::Start(spilled.offset))?;
+        self.file.read_exact(&mut body)?;
+        Ok(body)
+    }
+}
+
+impl SpilledBody {
+    fn end(&self) -> u64 {
+        self.offset + self.length as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spills_large_bodies() {
+        let mut store = SpillStore::new(4).unwrap();
+
+        assert_eq!(store.spill(b"tiny").unwrap(), None);
+
+        let first = store.spill(b"first body").unwrap().unwrap();
+        let second = store.spill(b"second body").unwrap().unwrap();
+
+        assert_eq!(store.read(&second).unwrap(), b"second body");
+        assert_eq!(store.read(&first).unwrap(), b"first body");
+
+        let third = store.spill(b"third body").unwrap().unwrap();
+        assert_eq!(store.read(&third).unwrap(), b"third body");
+        assert_eq!(store.read(&second).unwrap(), b"second body");
+    }
+
+    #[test]
+    fn reuses_released_space() {
+        let mut store = SpillStore::new(0).unwrap();
+        let first = store.spill(b"first").unwrap().unwrap();
+        let second = store.spill(b"second").unwrap().unwrap();
+        let third = store.spill(b"third").unwrap().unwrap();
+        let length = store.length;
+
+        // Neighbouring regions get merged, so larger bodies fit them
+        store.release(second);
+        store.release(first);
+        let larger = store.spill(b"larger one").unwrap().unwrap();
+        assert_eq!(larger.offset, first.offset);
+        assert_eq!(store.read(&larger).unwrap(), b"larger one");
+
+        // Leftovers get reused too
+        let tiny = store.spill(b"x").unwrap().unwrap();
+        assert_eq!(tiny.offset, larger.end());
+
+        assert_eq!(store.read(&third).unwrap(), b"third");
+        assert_eq!(store.length, length);
+    }
+}
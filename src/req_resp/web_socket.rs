@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+/// Payload of a WebSocket frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebSocketData {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// A recorded WebSocket frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSocketMessage {
+    /// Sent by the client (as opposed to by the server)
+    pub from_client: bool,
+    /// Time since the first frame of the connection
+    pub offset: Duration,
+    pub data: WebSocketData,
+}
+
+/// Convert the frames Chrome records in `_webSocketMessages`, ordered by time.
+/// Control frames are left out, they are handled by the WebSocket library.
+pub fn web_socket_messages(recorded: &[crate::har::WebSocketMessage]) -> Vec<WebSocketMessage> {
+    let start = recorded
+        .iter()
+        .map(|message| message.time)
+        .fold(f64::INFINITY, f64::min);
+
+    let mut messages: Vec<_> = recorded
+        .iter()
+        .filter_map(|message| {
+            let data = match message.opcode {
+                1 => WebSocketData::Text(message.data.clone()),
+                2 => match base64::decode(message.data.trim()) {
+                    Ok(data) => WebSocketData::Binary(data),
+                    Err(error) => {
+                        log::warn!("Dropping binary WebSocket frame, invalid base64: {}", error);
+                        return None;
+                    }
+                },
+                _ => return None,
+            };
+
+            Some(WebSocketMessage {
+                from_client: message.message_type.eq_ignore_ascii_case("send"),
+                offset: Duration::from_secs_f64((message.time - start).max(0.0)),
+                data,
+            })
+        })
+        .collect();
+
+    messages.sort_by_key(|message| message.offset);
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorded(
+        message_type: &str,
+        time: f64,
+        opcode: i64,
+        data: &str,
+    ) -> crate::har::WebSocketMessage {
+        crate::har::WebSocketMessage {
+            message_type: message_type.into(),
+            time,
+            opcode,
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn converts_recorded_frames() {
+        let messages = web_socket_messages(&[
+            recorded("receive", 1_589_000_001.5, 2, "AAEC"),
+            recorded("send", 1_589_000_000.0, 1, "hello"),
+            recorded("receive", 1_589_000_000.25, 1, "world"),
+            recorded("receive", 1_589_000_002.0, 9, ""),
+        ]);
+
+        assert_eq!(
+            messages,
+            vec![
+                WebSocketMessage {
+                    from_client: true,
+                    offset: Duration::from_secs(0),
+                    data: WebSocketData::Text("hello".into()),
+                },
+                WebSocketMessage {
+                    from_client: false,
+                    offset: Duration::from_millis(250),
+                    data: WebSocketData::Text("world".into()),
+                },
+                WebSocketMessage {
+                    from_client: false,
+                    offset: Duration::from_millis(1500),
+                    data: WebSocketData::Binary(vec![0, 1, 2]),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,513 @@
+//! Serving HTTPS, with PEM certificates or a self-signed one made up on start,
+//! and making up certificates for any host out of a local CA when proxying.
+
+use std::collections::HashMap;
+use std::env;
+use std::error::Error as StdError;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration, Utc};
+
+use hyper::{
+    server::conn::Http, service::Service, Body as HttpBody, Request as HttpRequest,
+    Response as HttpResponse,
+};
+use rcgen::{
+    BasicConstraints, CertificateParams, DistinguishedName, DnType, IsCa, KeyPair, SanType,
+};
+use rustls::{internal::pemfile, Certificate, NoClientAuth, PrivateKey, ServerConfig, Session};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use crate::errors::*;
+
+/// How long to wait before accepting connections again after failing to
+const ACCEPT_ERROR_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// TLS setup for the certificate chain in `cert` and its private key in
+/// `key`, both PEM files
+pub fn pem_config(cert: &Path, key: &Path) -> Result<ServerConfig, AppError> {
+    let certs = pemfile::certs(&mut open(cert)?).unwrap_or_default();
+    ensure!(
+        !certs.is_empty(),
+        TlsPem {
+            what: "certificates",
+            path: cert
+        }
+    );
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key)?).unwrap_or_default();
+    }
+    let key = keys.into_iter().next().context(TlsPem {
+        what: "private keys",
+        path: key,
+    })?;
+
+    server_config(certs, key)
+}
+
+/// TLS setup for a new self-signed certificate, valid for `hostnames` (which
+/// may be IP addresses as well)
+pub fn self_signed_config(hostnames: &[String]) -> Result<ServerConfig, AppError> {
+    let mut params = CertificateParams::default();
+    params.subject_alt_names = hostnames.iter().map(|hostname| san(hostname)).collect();
+    let certificate = rcgen::Certificate::from_params(params).context(SelfSigned)?;
+
+    server_config(
+        vec![Certificate(
+            certificate.serialize_der().context(SelfSigned)?,
+        )],
+        PrivateKey(certificate.serialize_private_key_der()),
+    )
+}
+
+fn san(hostname: &str) -> SanType {
+    match hostname.parse::<IpAddr>() {
+        Ok(address) => SanType::IpAddress(address),
+        Err(_) => SanType::DnsName(hostname.into()),
+    }
+}
+
+/// A local CA, making up certificates for whichever hosts clients connect to
+/// when intercepting HTTPS
+pub struct CertificateAuthority {
+    certificate: rcgen::Certificate,
+    /// TLS setups by host, so each certificate is only made up once
+    configs: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+impl CertificateAuthority {
+    /// The CA in `directory` (as `ca.pem` and `ca-key.pem`), made up and saved
+    /// there if there's none yet
+    pub fn open(directory: &Path) -> Result<Self, AppError> {
+        let (cert_path, key_path) = (directory.join("ca.pem"), directory.join("ca-key.pem"));
+
+        let certificate = if key_path.exists() {
+            let metadata = fs::metadata(&key_path).context(TlsFile { path: &key_path })?;
+            ensure!(is_private(&metadata), CaKeyExposed { path: &key_path });
+            let key = fs::read_to_string(&key_path).context(TlsFile { path: &key_path })?;
+            let key_pair = KeyPair::from_pem(&key).context(CaKey { path: &key_path })?;
+            // Same name and key as the saved certificate, so the certificates
+            // it signs check out against that one
+            rcgen::Certificate::from_params(ca_params(Some(key_pair))).context(SelfSigned)?
+        } else {
+            let certificate =
+                rcgen::Certificate::from_params(ca_params(None)).context(SelfSigned)?;
+            fs::create_dir_all(directory).context(TlsFile { path: directory })?;
+            let pem = certificate.serialize_pem().context(SelfSigned)?;
+            fs::write(&cert_path, pem).context(TlsFile { path: &cert_path })?;
+            write_private(&key_path, &certificate.serialize_private_key_pem())
+                .context(TlsFile { path: &key_path })?;
+            log::warn!(
+                "Made up a new CA, clients have to trust {:?} for intercepting HTTPS",
+                cert_path
+            );
+            certificate
+        };
+
+        Ok(Self {
+            certificate,
+            configs: Mutex::default(),
+        })
+    }
+
+    /// TLS setup for `host`, with a certificate signed by this CA
+    pub fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>, AppError> {
+        let mut configs = self.configs.lock().map_err(|_| AppError::DatabaseLock)?;
+        if let Some(config) = configs.get(host) {
+            return Ok(config.clone());
+        }
+
+        let mut params = CertificateParams::default();
+        params.subject_alt_names = vec![san(host)];
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, host);
+        // Clients refuse certificates valid for too long
+        params.not_before = Utc::now() - Duration::days(1);
+        params.not_after = Utc::now() + Duration::days(365);
+        params.use_authority_key_identifier_extension = true;
+        let leaf = rcgen::Certificate::from_params(params).context(Signing { host })?;
+
+        let config = Arc::new(server_config(
+            vec![Certificate(
+                leaf.serialize_der_with_signer(&self.certificate)
+                    .context(Signing { host })?,
+            )],
+            PrivateKey(leaf.serialize_private_key_der()),
+        )?);
+        configs.insert(host.into(), config.clone());
+        Ok(config)
+    }
+}
+
+/// Where the proxy CA goes by default: `harplay/ca` in the user's
+/// configuration directory (`$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`)
+pub fn default_ca_directory() -> PathBuf {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from));
+    match config {
+        Some(config) => config.join("harplay").join("ca"),
+        None => PathBuf::from("harplay-ca"),
+    }
+}
+
+/// Create `path` with `contents`, readable by the current user only
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Whether the file is out of other users' reach; only told on Unix
+#[cfg(unix)]
+fn is_private(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o077 == 0
+}
+
+#[cfg(not(unix))]
+fn is_private(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+fn ca_params(key_pair: Option<KeyPair>) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "harPlay CA");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "harPlay");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_pair = key_pair;
+    params
+}
+
+fn server_config(certs: Vec<Certificate>, key: PrivateKey) -> Result<ServerConfig, AppError> {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key).context(TlsConfig)?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, AppError> {
+    Ok(BufReader::new(File::open(path).context(TlsFile { path })?))
+}
+
+/// Serve HTTPS on `address` following `http`, with a service from
+/// `new_service` for every connection. Handshakes happen off the accepting
+/// loop, so slow clients don't hold the others back.
+pub async fn serve<F, S>(
+    address: &SocketAddr,
+    config: ServerConfig,
+    http: Http,
+    new_service: F,
+) -> Result<(), std::io::Error>
+where
+    F: Fn() -> S,
+    S: Service<HttpRequest<HttpBody>, Response = HttpResponse<HttpBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    // Bound like `hyper::Server::bind` does, through the standard library
+    let listener = std::net::TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    let mut listener = TcpListener::from_std(listener)?;
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            // Running out of file descriptors and such doesn't get better by
+            // trying again right away, so back off like `hyper::Server` does;
+            // connections dropped before being accepted are only theirs though
+            Err(error) if is_connection_error(&error) => {
+                log::debug!("Error accepting a connection: {}", error);
+                continue;
+            }
+            Err(error) => {
+                log::error!("Error accepting a connection: {}", error);
+                tokio::time::delay_for(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let http = http.clone();
+        let service = new_service();
+
+        tokio::spawn(async move {
+            match acceptor.accept(stream).await {
+                Ok(stream) => serve_connection(stream, http, service, peer).await,
+                Err(error) => log::debug!("TLS handshake with {} failed: {}", peer, error),
+            }
+        });
+    }
+}
+
+fn is_connection_error(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        error.kind(),
+        ConnectionRefused | ConnectionAborted | ConnectionReset
+    )
+}
+
+/// Serve the TLS connection `stream` following `http`, over HTTP/2 when that's
+/// what the client negotiated
+pub async fn serve_connection<IO, S>(
+    stream: TlsStream<IO>,
+    mut http: Http,
+    service: S,
+    peer: impl Display,
+) where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<HttpRequest<HttpBody>, Response = HttpResponse<HttpBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    // Clients that negotiated HTTP/2 skip its connection preface
+    let http2 = stream.get_ref().1.get_alpn_protocol() == Some(b"h2");
+    log::debug!(
+        "Serving {} over {}",
+        peer,
+        if http2 { "HTTP/2" } else { "HTTP/1.1" }
+    );
+
+    if let Err(error) = http
+        .http2_only(http2)
+        .serve_connection(stream, service)
+        .with_upgrades()
+        .await
+    {
+        log::debug!("Error serving {}: {}", peer, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use std::io::{Error as IoError, ErrorKind};
+
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(IoError::from(ErrorKind::ConnectionAborted), true; "aborted")]
+    #[test_case(IoError::from(ErrorKind::ConnectionReset), true; "reset")]
+    #[test_case(IoError::from_raw_os_error(24), false; "out of file descriptors")]
+    fn it_tells_connection_errors(error: IoError, expected: bool) {
+        assert_eq!(is_connection_error(&error), expected);
+    }
+
+    #[test]
+    fn it_makes_up_certificates() {
+        let config = self_signed_config(&["localhost".into(), "127.0.0.1".into()]).unwrap();
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_keeps_the_ca_key_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let key = directory.path().join("ca-key.pem");
+        CertificateAuthority::open(directory.path()).unwrap();
+        let mode = fs::metadata(&key).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_matches!(
+            CertificateAuthority::open(directory.path()).err(),
+            Some(AppError::CaKeyExposed { .. })
+        );
+    }
+
+    #[test]
+    fn it_loads_pem_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let (cert, key) = (
+            directory.path().join("cert.pem"),
+            directory.path().join("key.pem"),
+        );
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        fs::write(&cert, certificate.serialize_pem().unwrap()).unwrap();
+        fs::write(&key, certificate.serialize_private_key_pem()).unwrap();
+
+        assert!(pem_config(&cert, &key).is_ok());
+        assert_matches!(
+            pem_config(&key, &key).err(),
+            Some(AppError::TlsPem {
+                what: "certificates",
+                ..
+            })
+        );
+        assert_matches!(
+            pem_config(&cert, &cert).err(),
+            Some(AppError::TlsPem {
+                what: "private keys",
+                ..
+            })
+        );
+        assert_matches!(
+            pem_config(&directory.path().join("missing.pem"), &key).err(),
+            Some(AppError::TlsFile { .. })
+        );
+    }
+
+    /// Run a TLS handshake between a client trusting `ca` and `server`
+    fn handshake(ca: &Path, host: &str, server: Arc<ServerConfig>) -> Result<(), rustls::TLSError> {
+        use rustls::{ClientConfig, ClientSession, ServerSession};
+
+        let mut client_config = ClientConfig::new();
+        client_config
+            .root_store
+            .add_pem_file(&mut open(ca).unwrap())
+            .unwrap();
+        let name = webpki::DNSNameRef::try_from_ascii_str(host).unwrap();
+        let mut client = ClientSession::new(&Arc::new(client_config), name);
+        let mut server = ServerSession::new(&server);
+
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut buffer = Vec::new();
+            client.write_tls(&mut buffer).unwrap();
+            server.read_tls(&mut &buffer[..]).unwrap();
+            server.process_new_packets()?;
+
+            let mut buffer = Vec::new();
+            server.write_tls(&mut buffer).unwrap();
+            client.read_tls(&mut &buffer[..]).unwrap();
+            client.process_new_packets()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn it_signs_certificates_clients_trust() {
+        let directory = tempfile::tempdir().unwrap();
+        let ca_path = directory.path().join("ca");
+
+        let ca = CertificateAuthority::open(&ca_path).unwrap();
+        let config = ca.server_config("api.example.com").unwrap();
+        assert!(Arc::ptr_eq(
+            &config,
+            &ca.server_config("api.example.com").unwrap()
+        ));
+        assert!(handshake(&ca_path.join("ca.pem"), "api.example.com", config).is_ok());
+
+        // Saved CAs sign certificates that check out against the saved one
+        let reopened = CertificateAuthority::open(&ca_path).unwrap();
+        let config = reopened.server_config("cdn.example.com").unwrap();
+        assert!(handshake(&ca_path.join("ca.pem"), "cdn.example.com", config.clone()).is_ok());
+        assert!(handshake(&ca_path.join("ca.pem"), "api.example.com", config).is_err());
+
+        let other = CertificateAuthority::open(&directory.path().join("other")).unwrap();
+        let config = other.server_config("api.example.com").unwrap();
+        assert!(handshake(&ca_path.join("ca.pem"), "api.example.com", config).is_err());
+    }
+
+    #[test]
+    fn it_serves_concurrent_http2_streams() {
+        use futures_util::future;
+        use hyper::client::conn::Builder;
+        use hyper::service::service_fn;
+        use rustls::ClientConfig;
+        use tokio::net::TcpStream;
+        use tokio::runtime::Runtime;
+        use tokio_rustls::TlsConnector;
+
+        let directory = tempfile::tempdir().unwrap();
+        let ca = CertificateAuthority::open(directory.path()).unwrap();
+        let config = ca.server_config("localhost").unwrap();
+        // `serve` binds the address itself
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        Runtime::new().unwrap().block_on(async move {
+            // Slow enough for streams one after the other to show
+            let service = || {
+                service_fn(|request: HttpRequest<HttpBody>| async move {
+                    tokio::time::delay_for(std::time::Duration::from_millis(200)).await;
+                    let body = format!("{:?} {}", request.version(), request.uri().path());
+                    Ok::<_, hyper::Error>(HttpResponse::new(HttpBody::from(body)))
+                })
+            };
+            let config = (*config).clone();
+            tokio::spawn(async move { serve(&address, config, Http::new(), service).await });
+
+            let mut client_config = ClientConfig::new();
+            client_config
+                .root_store
+                .add_pem_file(&mut open(&directory.path().join("ca.pem")).unwrap())
+                .unwrap();
+            client_config.set_protocols(&[b"h2".to_vec()]);
+            // Until `serve` is listening
+            let stream = loop {
+                match std::net::TcpStream::connect(address) {
+                    Ok(stream) => break TcpStream::from_std(stream).unwrap(),
+                    Err(_) => tokio::time::delay_for(std::time::Duration::from_millis(10)).await,
+                }
+            };
+            let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+            let stream = TlsConnector::from(Arc::new(client_config))
+                .connect(name, stream)
+                .await
+                .unwrap();
+            assert_eq!(stream.get_ref().1.get_alpn_protocol(), Some(&b"h2"[..]));
+
+            let (mut client, connection) = Builder::new()
+                .http2_only(true)
+                .handshake::<_, HttpBody>(stream)
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+
+            let start = std::time::Instant::now();
+            let mut responses = Vec::new();
+            for index in 0..10 {
+                future::poll_fn(|context| client.poll_ready(context))
+                    .await
+                    .unwrap();
+                let request = HttpRequest::get(format!("https://localhost/{}", index))
+                    .body(HttpBody::empty())
+                    .unwrap();
+                responses.push(client.send_request(request));
+            }
+            let responses = future::join_all(responses.into_iter().map(|response| async {
+                let response = response.await.unwrap();
+                hyper::body::to_bytes(response.into_body()).await.unwrap()
+            }))
+            .await;
+
+            for (index, body) in responses.iter().enumerate() {
+                assert_eq!(body, &format!("HTTP/2.0 /{}", index));
+            }
+            // All over one connection at once, rather than one after the other
+            assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+        });
+    }
+}